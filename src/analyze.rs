@@ -0,0 +1,397 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::{Duration, NaiveDateTime, Timelike};
+use tabled::{Style, Table, Tabled};
+
+use crate::{
+    format_micros, parse_percent, sketch::RttSketch, stats::percentile, stats_table, Report,
+    RttStats, TargetReport,
+};
+
+/// Bounds of the RTT ranges of the hourly histogram in µs.
+const BINS: [u32; 3] = [30_000, 50_000, 100_000];
+/// IPs of a host shown in titles at most.
+const MAX_IPS: usize = 3;
+
+#[derive(clap::Args, Debug)]
+pub struct AnalyzeArgs {
+    /// Length of the time window in h that ends with the latest ping of each host.
+    #[clap(long, default_value_t = 24)]
+    recent: u32,
+
+    /// Percentiles of the RTTs to show, from 0 to 100.
+    #[clap(
+        long,
+        value_parser = parse_percent,
+        use_value_delimiter = true,
+        default_value = "50,95"
+    )]
+    percentiles: Vec<f64>,
+
+    /// Directory with JSON reports of any version, other files are skipped.
+    dir: PathBuf,
+}
+
+/// The pings of a host with one probe collected from many reports.
+pub struct Dataset {
+    pub host_name: String,
+    /// What the pings measured, see [`probe`].
+    pub probe: String,
+    /// All IPs the host name resolved to, in the order they were seen.
+    pub ips: Vec<String>,
+    /// Wall clock time at which each ping was sent and its RTT in µs, `None` if lost. Times
+    /// are local to the machine that wrote the report and sorted.
+    pub pings: Vec<(NaiveDateTime, Option<u32>)>,
+    /// The RTTs of the reports that only kept a sketch of them, merged.
+    pub sketch: RttSketch,
+}
+
+impl Dataset {
+    /// `host (ip, ...) probe` or only the IPs and probe if the host has no name.
+    pub fn title(&self) -> String {
+        let mut ips = self.ips[..self.ips.len().min(MAX_IPS)].join(", ");
+        if self.ips.len() > MAX_IPS {
+            ips += ", ...";
+        }
+
+        if self.host_name.is_empty() {
+            format!("{ips} {}", self.probe)
+        } else {
+            format!("{} ({ips}) {}", self.host_name, self.probe)
+        }
+    }
+}
+
+/// What the pings of a target measured: the protocol and port, e.g. `TCP 443`, the URL of HTTP
+/// targets or the query of DNS targets.
+pub fn probe(target: &TargetReport) -> String {
+    match (&target.url, &target.query) {
+        (Some(url), _) => url.clone(),
+        (None, Some(query)) => format!("{} {query}", target.probe_name()),
+        (None, None) => target.probe_name(),
+    }
+}
+
+/// Reads a report or all reports in a directory. Files in the directory that aren't reports are
+/// skipped with a message.
+pub fn load_reports(path: &Path) -> Result<Vec<Report>, String> {
+    let mut paths: Vec<PathBuf> = if path.is_dir() {
+        let entries =
+            fs::read_dir(path).map_err(|e| format!("Unable to read '{}': {e}", path.display()))?;
+        entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.is_file() && p.extension().is_some_and(|e| e == "json"))
+            .collect()
+    } else if path.is_file() {
+        vec![path.into()]
+    } else {
+        return Err(format!("'{}' doesn't exist.", path.display()));
+    };
+    paths.sort();
+
+    let reports = paths.iter().filter_map(|path| {
+        let report = fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|json| serde_json::from_str(&json).map_err(|e| e.to_string()));
+
+        match report {
+            Ok(report) => Some(report),
+            Err(e) => {
+                println!("Skipping '{}': {e}", path.display());
+                None
+            }
+        }
+    });
+
+    Ok(reports.collect())
+}
+
+/// The wall clock time at which a report started, in the time zone of the machine that wrote it.
+pub fn start_time(report: &Report) -> Option<NaiveDateTime> {
+    let start = chrono::DateTime::parse_from_rfc3339(&report.start_time).ok()?;
+    Some(start.naive_local())
+}
+
+/// The wall clock time of each ping of a target and its RTT in µs, `None` if lost.
+pub fn target_pings(
+    start: NaiveDateTime,
+    target: &TargetReport,
+) -> impl Iterator<Item = (NaiveDateTime, Option<u32>)> + '_ {
+    target.pings.iter().map(move |p| {
+        let time = start + Duration::microseconds(p.started_at as i64);
+        (time, p.rtt())
+    })
+}
+
+/// Merges the pings of all reports per host name, or per IP for targets without a name, and
+/// probe, so that e.g. ICMP and HTTPS pings of a host are kept apart.
+pub fn join_datasets(reports: &[Report]) -> Vec<Dataset> {
+    let mut keys: HashMap<(&str, String), usize> = HashMap::new();
+    let mut sets: Vec<Dataset> = Vec::new();
+
+    for report in reports {
+        let Some(start) = start_time(report) else {
+            println!(
+                "Skipping a report with invalid start time '{}'",
+                report.start_time
+            );
+            continue;
+        };
+
+        for target in &report.targets {
+            let key = if target.host_name.is_empty() {
+                &target.ip
+            } else {
+                &target.host_name
+            };
+            let idx = *keys.entry((key, probe(target))).or_insert_with(|| {
+                sets.push(Dataset {
+                    host_name: target.host_name.clone(),
+                    probe: probe(target),
+                    ips: Vec::new(),
+                    pings: Vec::new(),
+                    sketch: RttSketch::default(),
+                });
+                sets.len() - 1
+            });
+            let set = &mut sets[idx];
+
+            if !set.ips.contains(&target.ip) {
+                set.ips.push(target.ip.clone());
+            }
+            set.pings.extend(target_pings(start, target));
+            if let Some(sketch) = &target.sketch {
+                set.sketch.merge(sketch);
+            }
+        }
+    }
+
+    for set in &mut sets {
+        set.pings.sort_by_key(|&(time, _)| time);
+    }
+
+    sets
+}
+
+/// Keeps only the pings in the `span` before the latest ping of each dataset. Sketches don't know
+/// when their pings were sent, so they are dropped.
+pub fn take_recent(datasets: &mut [Dataset], span: Duration) {
+    for set in datasets {
+        set.sketch = RttSketch::default();
+        if let Some(&(last, _)) = set.pings.last() {
+            set.pings.retain(|&(time, _)| time > last - span);
+        }
+    }
+}
+
+/// Groups the pings by the hour of day they were sent, rounded to the nearest hour.
+fn group_by_time_of_day(set: &Dataset) -> Vec<(u32, Vec<Option<u32>>)> {
+    let mut hours: Vec<Vec<Option<u32>>> = vec![Vec::new(); 24];
+
+    for &(time, rtt) in &set.pings {
+        let hour = (time + Duration::minutes(30)).hour();
+        hours[hour as usize].push(rtt);
+    }
+
+    (0..24)
+        .zip(hours)
+        .filter(|(_, pings)| !pings.is_empty())
+        .collect()
+}
+
+/// Share of the replies in each RTT range, separated by [`BINS`].
+fn histogram(rtts: &[u32]) -> [f64; BINS.len() + 1] {
+    let mut counts = [0usize; BINS.len() + 1];
+    for &rtt in rtts {
+        counts[BINS.iter().take_while(|&&b| rtt >= b).count()] += 1;
+    }
+
+    counts.map(|c| c as f64 / rtts.len().max(1) as f64)
+}
+
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+struct HostStats {
+    #[tabled(rename = "Target")]
+    host: String,
+    from: String,
+    to: String,
+    #[tabled(inline)]
+    rtt: RttStats,
+    #[tabled(rename = ">60 ms")]
+    above60: String,
+    #[tabled(rename = ">100 ms")]
+    above100: String,
+}
+
+impl HostStats {
+    fn new(set: &Dataset, percentiles: &[f64]) -> Self {
+        let rtts: Vec<u32> = set.pings.iter().filter_map(|&(_, rtt)| rtt).collect();
+        let above = |ms: u32| {
+            let count = rtts.iter().filter(|&&rtt| rtt > ms * 1000).count() as u64
+                + set.sketch.replies_above(ms * 1000);
+            let replies = rtts.len() as u64 + set.sketch.replies();
+            format!("{:>6.2} %", count as f64 / replies.max(1) as f64 * 100.0)
+        };
+        let time = |ping: Option<&(NaiveDateTime, _)>| match ping {
+            Some((time, _)) => time.format("%F %H:%M").to_string(),
+            None => "-".into(),
+        };
+
+        Self {
+            host: set.title(),
+            from: time(set.pings.first()),
+            to: time(set.pings.last()),
+            above60: above(60),
+            above100: above(100),
+            rtt: if set.sketch.pings == 0 {
+                RttStats::from_rtts(set.pings.len(), rtts, percentiles)
+            } else {
+                let mut sketch = set.sketch.clone();
+                set.pings.iter().for_each(|&(_, rtt)| sketch.record(rtt));
+                RttStats::from_sketch(&sketch, percentiles)
+            },
+        }
+    }
+}
+
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+struct HourStats {
+    hour: String,
+    pings: usize,
+    #[tabled(rename = "Packet Loss")]
+    packet_loss: String,
+    median: String,
+    #[tabled(rename = "<30 ms")]
+    below30: String,
+    #[tabled(rename = "30-50 ms")]
+    below50: String,
+    #[tabled(rename = "50-100 ms")]
+    below100: String,
+    #[tabled(rename = ">=100 ms")]
+    above100: String,
+}
+
+impl HourStats {
+    fn new(hour: u32, pings: &[Option<u32>]) -> Self {
+        let mut rtts: Vec<u32> = pings.iter().flatten().copied().collect();
+        rtts.sort();
+        let percent = |share: f64| format!("{:>6.2} %", share * 100.0);
+        let [below30, below50, below100, above100] = histogram(&rtts).map(percent);
+
+        Self {
+            hour: format!("{hour:>2} h"),
+            pings: pings.len(),
+            packet_loss: percent(1.0 - rtts.len() as f64 / pings.len() as f64),
+            median: match percentile(&rtts, 50.0) {
+                Some(rtt) => format_micros(rtt),
+                None => "-".into(),
+            },
+            below30,
+            below50,
+            below100,
+            above100,
+        }
+    }
+}
+
+/// Prints the statistics of all hosts in the reports of a directory, like `analysis.ipynb`.
+pub fn analyze(args: &AnalyzeArgs) -> Result<(), String> {
+    let reports = load_reports(&args.dir)?;
+    let mut datasets = join_datasets(&reports);
+
+    if datasets.is_empty() {
+        return Err(format!("No reports found in '{}'.", args.dir.display()));
+    }
+
+    let ping_count: u64 = datasets
+        .iter()
+        .map(|set| set.pings.len() as u64 + set.sketch.pings)
+        .sum();
+    println!(
+        "{} report(s) with {ping_count} ping(s) of {} target(s)",
+        reports.len(),
+        datasets.len()
+    );
+
+    let host_stats = |datasets: &[Dataset]| {
+        let stats: Vec<_> = datasets
+            .iter()
+            .map(|set| HostStats::new(set, &args.percentiles))
+            .collect();
+        stats_table(&stats, &args.percentiles, |s| &s.rtt.percentiles)
+    };
+    println!("All pings:\n{}", host_stats(&datasets));
+
+    for set in &datasets {
+        let stats: Vec<_> = group_by_time_of_day(set)
+            .iter()
+            .map(|(hour, pings)| HourStats::new(*hour, pings))
+            .collect();
+        let table = Table::new(&stats).with(Style::modern());
+        println!("By hour of day for {}:\n{table}", set.title());
+    }
+
+    take_recent(&mut datasets, Duration::hours(args.recent.into()));
+    println!(
+        "Last {} h of each target:\n{}",
+        args.recent,
+        host_stats(&datasets)
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn probes_of_a_host_are_kept_apart() {
+        let target = |protocol: &str, port: Option<u16>, url: Option<&str>| {
+            json!({
+                "host_name": "example.com",
+                "ip": "93.184.216.34",
+                "protocol": protocol,
+                "port": port,
+                "url": url,
+                "pings": [{ "started_at": 0, "status": "ok", "rtt": 1000 }]
+            })
+        };
+        let report = |ip: &str| {
+            let mut icmp = target("icmp", None, None);
+            icmp["ip"] = ip.into();
+            serde_json::from_value(json!({
+                "version": 2,
+                "start_time": "2024-03-01T08:00:00+00:00",
+                "duration": 1,
+                "interval": 500,
+                "targets": [
+                    icmp,
+                    target("tcp", Some(443), None),
+                    target("https", Some(443), Some("https://example.com:443/")),
+                ]
+            }))
+            .unwrap()
+        };
+
+        let sets = join_datasets(&[report("93.184.216.34"), report("93.184.216.35")]);
+        let titles: Vec<String> = sets.iter().map(Dataset::title).collect();
+        assert_eq!(
+            titles,
+            [
+                "example.com (93.184.216.34, 93.184.216.35) ICMP",
+                "example.com (93.184.216.34) TCP 443",
+                "example.com (93.184.216.34) https://example.com:443/",
+            ]
+        );
+        assert!(sets.iter().all(|s| s.pings.len() == 2));
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::TargetReport;
+
+/// Rounds in which at least two targets were failing. Times are the `started_at` of pings.
+#[derive(Serialize, Deserialize)]
+pub struct SharedOutage {
+    /// When the first round of the outage started.
+    pub start: u64,
+    /// When the round after the outage started, or its last round if the run ended meanwhile.
+    pub end: u64,
+    pub duration: u64,
+    /// Indices of the targets that lost pings meanwhile, in the order they were given.
+    pub targets: BTreeSet<usize>,
+    /// The nearest of them, i.e. the first one, which is the most likely cause.
+    pub cause: usize,
+}
+
+/// The pings of all targets that were sent in one round of the schedule.
+struct Round {
+    /// When the first of them was sent.
+    start: u64,
+    /// The target of each ping and, if it was lost, the index of the outage of the target it
+    /// belongs to.
+    pings: Vec<(usize, Option<usize>)>,
+}
+
+/// Lines up the pings of all targets by the round of the schedule in which they were sent, finds
+/// the rounds in which several targets were failing and attributes each outage of a target to
+/// the nearest target that failed at the same time.
+///
+/// A target counts as failing from a lost ping until it replies again. Lost pings block until
+/// they time out, so a failing target skips rounds when the timeout is longer than the interval
+/// and still counts as failing in them.
+///
+/// Targets are assumed to be given from near to far, e.g. the gateway, then a router of the ISP,
+/// then a public server. An outage of a target is attributed to the first target that was failing
+/// for at least half of the lost pings of the outage, which is the target itself if no nearer
+/// one was. The outages in the stats of the targets are updated accordingly.
+pub fn correlate(interval: u32, targets: &mut [TargetReport]) -> Vec<SharedOutage> {
+    let interval = interval.max(1) as u64 * 1000;
+    let round = |started_at: u64| (started_at + interval / 2) / interval;
+
+    let mut rounds: BTreeMap<u64, Round> = BTreeMap::new();
+    for (i, t) in targets.iter().enumerate() {
+        // lost pings that follow a reply start the next outage
+        let mut outage_count = 0;
+        let mut failing = false;
+
+        for ping in &t.pings {
+            let lost = ping.rtt().is_none();
+            if lost && !failing {
+                outage_count += 1;
+            }
+            failing = lost;
+
+            let round = rounds.entry(round(ping.started_at)).or_insert(Round {
+                start: ping.started_at,
+                pings: Vec::new(),
+            });
+            round.start = round.start.min(ping.started_at);
+            round.pings.push((i, lost.then(|| outage_count - 1)));
+        }
+    }
+
+    // for each outage of each target, how many of its lost pings each nearer target was failing
+    let mut shared_losses: Vec<Vec<Vec<usize>>> = targets
+        .iter()
+        .enumerate()
+        .map(|(i, t)| {
+            let outage_count = t.stats.as_ref().map_or(0, |s| s.outages.len());
+            vec![vec![0; i]; outage_count]
+        })
+        .collect();
+    let mut failing = vec![false; targets.len()];
+    let mut outages: Vec<SharedOutage> = Vec::new();
+    let mut current: Option<SharedOutage> = None;
+    let mut last_start = 0;
+
+    for round in rounds.values() {
+        for &(i, outage) in &round.pings {
+            failing[i] = outage.is_some();
+        }
+        for &(i, outage) in &round.pings {
+            let Some(losses) = outage.and_then(|k| shared_losses[i].get_mut(k)) else {
+                continue;
+            };
+            for (c, count) in losses.iter_mut().enumerate() {
+                *count += failing[c] as usize;
+            }
+        }
+
+        let lost: BTreeSet<usize> = (0..failing.len()).filter(|&t| failing[t]).collect();
+        match &mut current {
+            Some(outage) if lost.len() >= 2 => outage.targets.extend(lost),
+            None if lost.len() >= 2 => {
+                current = Some(SharedOutage {
+                    start: round.start,
+                    end: round.start,
+                    duration: 0,
+                    targets: lost,
+                    cause: 0,
+                })
+            }
+            // the round that ends an outage
+            Some(_) => outages.extend(current.take().map(|o| SharedOutage {
+                end: round.start,
+                ..o
+            })),
+            None => {}
+        }
+        last_start = round.start;
+    }
+    outages.extend(current.map(|o| SharedOutage {
+        end: last_start,
+        ..o
+    }));
+
+    for outage in &mut outages {
+        outage.cause = *outage.targets.first().expect("at least two targets");
+        outage.duration = outage.end - outage.start;
+    }
+
+    for (i, t) in targets.iter_mut().enumerate() {
+        let Some(stats) = &mut t.stats else {
+            continue;
+        };
+
+        for (outage, losses) in stats.outages.iter_mut().zip(&shared_losses[i]) {
+            let cause = losses.iter().position(|&shared| shared * 2 >= outage.lost);
+            outage.cause = Some(cause.unwrap_or(i));
+        }
+    }
+
+    outages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{stats::Stats, PingResult, Probe, Status, Target};
+
+    const INTERVAL: u32 = 500;
+
+    /// A target with pings sent at the given ms, `true` if they got a reply.
+    fn target(idx: usize, pings: &[(u64, bool)]) -> TargetReport {
+        let target = Target {
+            ip: [10, 0, 0, idx as u8 + 1].into(),
+            host: None,
+            probe: Probe::Udp(7),
+        };
+        let mut report = TargetReport::new(idx, &target);
+        report.pings = pings
+            .iter()
+            .map(|&(ms, replied)| PingResult {
+                started_at: ms * 1000,
+                status: if replied {
+                    Status::Ok { rtt: 300 }
+                } else {
+                    Status::Timeout
+                },
+                details: Default::default(),
+            })
+            .collect();
+        report.stats = Some(Stats::new(&report, &[], None));
+        report
+    }
+
+    /// Pings from `from` to `to` ms every `every` ms.
+    fn pings(from: u64, to: u64, every: u64, replied: bool) -> Vec<(u64, bool)> {
+        (from..to)
+            .step_by(every as usize)
+            .map(|ms| (ms, replied))
+            .collect()
+    }
+
+    fn causes(target: &TargetReport) -> Vec<Option<usize>> {
+        let outages = &target.stats.as_ref().unwrap().outages;
+        outages.iter().map(|o| o.cause).collect()
+    }
+
+    #[test]
+    fn timeouts_longer_than_the_interval_dont_split_outages() {
+        // lost pings block for a timeout of 1 s, so they are only sent every other round
+        let lost = [pings(0, 5000, 1000, false), pings(5000, 6000, 500, true)].concat();
+        let mut targets = [
+            target(0, &lost),
+            target(1, &lost),
+            target(2, &pings(0, 6000, 500, true)),
+        ];
+
+        let outages = correlate(INTERVAL, &mut targets);
+
+        assert_eq!(outages.len(), 1);
+        assert_eq!((outages[0].start, outages[0].end), (0, 5_000_000));
+        assert_eq!(outages[0].duration, 5_000_000);
+        assert_eq!(outages[0].targets, BTreeSet::from([0, 1]));
+        assert_eq!(outages[0].cause, 0);
+        assert_eq!(causes(&targets[0]), [Some(0)]);
+        assert_eq!(causes(&targets[1]), [Some(0)]);
+    }
+
+    #[test]
+    fn outages_at_the_end_of_the_run_end_with_its_last_round() {
+        let mut targets = [
+            target(0, &pings(0, 3000, 1000, false)),
+            target(1, &pings(0, 3000, 500, false)),
+        ];
+
+        let outages = correlate(INTERVAL, &mut targets);
+
+        assert_eq!(outages.len(), 1);
+        assert_eq!((outages[0].start, outages[0].end), (0, 2_500_000));
+    }
+
+    #[test]
+    fn outages_are_attributed_to_the_nearest_failing_target() {
+        let mut targets = [
+            // the gateway fails once, the far target was pinged a little earlier in that round
+            target(0, &[(0, true), (501, false), (1000, true), (1500, true)]),
+            target(1, &[(0, true), (500, false), (1000, true), (1500, false)]),
+        ];
+
+        let outages = correlate(INTERVAL, &mut targets);
+
+        assert_eq!(outages.len(), 1);
+        assert_eq!((outages[0].start, outages[0].end), (500_000, 1_000_000));
+        assert_eq!(causes(&targets[0]), [Some(0)]);
+        // the second outage of the far target is its own
+        assert_eq!(causes(&targets[1]), [Some(0), Some(1)]);
+    }
+}
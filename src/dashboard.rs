@@ -0,0 +1,216 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    future::Future,
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+use async_std::task;
+use crossterm::{
+    cursor::MoveToPreviousLine,
+    queue,
+    terminal::{self, Clear, ClearType},
+};
+use futures::future::{select, Either};
+
+use crate::{format_micros, sketch::RttSketch, PingArgs, PingResult, TargetReport};
+
+/// How often the dashboard is redrawn.
+const REFRESH: Duration = Duration::from_millis(250);
+/// Pings shown in the sparkline at most, fewer if the terminal is too narrow.
+const SPARKLINE_LEN: usize = 60;
+const SPARKLINE_MIN_LEN: usize = 10;
+/// Width of the target column at most.
+const TITLE_LEN: usize = 40;
+/// Width of the loss bar.
+const LOSS_BAR_LEN: usize = 10;
+/// Sparkline levels from the lowest to the highest RTT.
+const LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+/// Sparkline mark of a lost ping.
+const LOST: char = '×';
+
+/// What the dashboard knows about the pings of a target so far.
+#[derive(Default)]
+struct TargetView {
+    /// The latest pings, `None` if lost.
+    recent: VecDeque<Option<u32>>,
+    /// All RTTs, which take little memory however long the run goes on.
+    rtts: RttSketch,
+}
+
+impl TargetView {
+    fn observe(&mut self, rtt: Option<u32>) {
+        if self.recent.len() == SPARKLINE_LEN {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(rtt);
+        self.rtts.record(rtt);
+    }
+
+    /// The latest `len` pings as bars scaled to the slowest of them.
+    fn sparkline(&self, len: usize) -> String {
+        let recent = self
+            .recent
+            .iter()
+            .skip(self.recent.len().saturating_sub(len));
+        let max = recent.clone().flatten().max().copied().unwrap_or_default();
+
+        recent
+            .map(|rtt| match rtt {
+                Some(rtt) => {
+                    let level = (*rtt as u64 * LEVELS.len() as u64).div_ceil(max.max(1) as u64);
+                    LEVELS[(level as usize).clamp(1, LEVELS.len()) - 1]
+                }
+                None => LOST,
+            })
+            .collect()
+    }
+
+    fn loss(&self) -> f64 {
+        match self.rtts.pings {
+            0 => 0.0,
+            pings => 1.0 - self.rtts.replies() as f64 / pings as f64,
+        }
+    }
+
+    fn percentile(&self, p: f64) -> String {
+        match self.rtts.percentile(p) {
+            Some(rtt) => format_micros(rtt),
+            None => "-".into(),
+        }
+    }
+}
+
+/// Shows the pings of all targets as they arrive, one row per target that is redrawn in place.
+///
+/// Only the cursor is moved, the terminal stays in its normal mode so that Ctrl-C stops the run
+/// as usual.
+pub struct Dashboard {
+    start_time: Instant,
+    /// Run time in s, 0 until interrupted.
+    duration: u32,
+    /// The title of each target and what is known about it, in the order of the targets.
+    targets: Vec<(String, RefCell<TargetView>)>,
+    /// Lines drawn the last time, which are overwritten the next time.
+    drawn: Cell<u16>,
+}
+
+impl Dashboard {
+    pub fn new(args: &PingArgs) -> Self {
+        let targets = args
+            .ips_or_host_names
+            .iter()
+            .map(|t| (t.to_string(), RefCell::default()))
+            .collect();
+
+        Self {
+            start_time: Instant::now(),
+            duration: args.duration(),
+            targets,
+            drawn: Cell::new(0),
+        }
+    }
+
+    pub fn observe(&self, report: &TargetReport, ping: &PingResult) {
+        if let Some((_, view)) = self.targets.get(report.idx) {
+            view.borrow_mut().observe(ping.rtt());
+        }
+    }
+
+    /// Redraws the dashboard until `run` completes and once more with its final state.
+    pub async fn show<F: Future>(&self, run: F) -> F::Output {
+        let mut run = Box::pin(run);
+
+        loop {
+            match select(run, Box::pin(task::sleep(REFRESH))).await {
+                Either::Left((output, _)) => {
+                    self.draw();
+                    return output;
+                }
+                Either::Right((_, pending)) => {
+                    run = pending;
+                    self.draw();
+                }
+            }
+        }
+    }
+
+    fn lines(&self, width: usize) -> Vec<String> {
+        let elapsed = self.start_time.elapsed().as_secs();
+        let mut lines = vec![match self.duration {
+            0 => format!("{elapsed} s, until interrupted"),
+            d => format!("{} s of {d} s", elapsed.min(d.into())),
+        }];
+
+        let title_len = self
+            .targets
+            .iter()
+            .map(|(title, _)| title.chars().count())
+            .max()
+            .unwrap_or_default()
+            .clamp("Target".len(), TITLE_LEN);
+        // everything but the sparkline, the spaces around it and the last column, which would
+        // wrap the line
+        let fixed = title_len + LOSS_BAR_LEN + 9 + 4 * 10 + 3;
+        let spark_len = width
+            .saturating_sub(fixed)
+            .clamp(SPARKLINE_MIN_LEN, SPARKLINE_LEN);
+
+        lines.push(format!(
+            "{:<title_len$} {:<spark_len$} {:<w$} {:>9} {:>9} {:>9} {:>9}",
+            "Target",
+            "Recent RTTs",
+            "Packet Loss",
+            "Min",
+            "Median",
+            "P95",
+            "Max",
+            w = LOSS_BAR_LEN + 9,
+        ));
+
+        for (title, view) in &self.targets {
+            let view = view.borrow();
+            let title: String = title.chars().take(title_len).collect();
+            let loss = view.loss();
+            let filled = (loss * LOSS_BAR_LEN as f64).ceil() as usize;
+            let bar = "█".repeat(filled) + &"░".repeat(LOSS_BAR_LEN - filled);
+
+            lines.push(format!(
+                "{title:<title_len$} {:<spark_len$} {bar} {:>6.2} % {:>9} {:>9} {:>9} {:>9}",
+                view.sparkline(spark_len),
+                loss * 100.0,
+                view.percentile(0.0),
+                view.percentile(50.0),
+                view.percentile(95.0),
+                view.percentile(100.0),
+            ));
+        }
+
+        lines
+    }
+
+    fn draw(&self) {
+        // some terminals don't know their size
+        let width = match terminal::size() {
+            Ok((w, _)) if w > 0 => w as usize,
+            _ => usize::MAX,
+        };
+        let lines = self.lines(width);
+        let mut stdout = io::stdout().lock();
+
+        // drawing only fails if stdout is gone, there is nowhere to tell about it then
+        if self.drawn.get() > 0 {
+            _ = queue!(stdout, MoveToPreviousLine(self.drawn.get()));
+        }
+        for line in &lines {
+            // longer lines would wrap and move the dashboard down with every redraw
+            let line: String = line.chars().take(width.saturating_sub(1)).collect();
+            _ = queue!(stdout, Clear(ClearType::CurrentLine));
+            _ = writeln!(stdout, "{}", line.trim_end());
+        }
+        _ = stdout.flush();
+
+        self.drawn.set(lines.len() as u16);
+    }
+}
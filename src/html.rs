@@ -0,0 +1,446 @@
+use std::{fmt::Write, fs, ops::Range, path::Path};
+
+use chrono::NaiveDateTime;
+use plotters::{coord::types::RangedDateTime, prelude::*};
+use tabled::Tabled;
+
+use crate::{
+    analyze::{probe, start_time, target_pings, Dataset},
+    plot::{concise_label, rtt_chart, time_range},
+    stats_cells, window_stats, HopStats, HttpStats, OutageStats, Report, SharedOutageStats,
+    TargetStats,
+};
+
+/// Width of the charts in px, they are scaled to the width of the page.
+const CHART_WIDTH: u32 = 1600;
+/// Height of the histogram and loss chart of a single target in px.
+const CHART_HEIGHT: u32 = 250;
+/// Upper bounds of the RTT ranges of the histogram in ms.
+const BINS: [f64; 12] = [
+    0.1, 0.2, 0.5, 1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0,
+];
+/// Time slots of the loss chart at most.
+const LOSS_SLOTS: usize = 120;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em auto; max-width: 1600px; color: #222; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: right; white-space: pre; }
+th { background: #f0f0f0; }
+svg { width: 100%; height: auto; }
+";
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The same table as in the terminal.
+fn table<T: Tabled>(rows: &[T]) -> String {
+    let cells = [T::headers()]
+        .into_iter()
+        .chain(rows.iter().map(Tabled::fields));
+    cells_table(cells.collect())
+}
+
+/// A table of cells, the first row are the headers.
+fn cells_table(cells: Vec<Vec<String>>) -> String {
+    let mut rows = cells.into_iter();
+    let mut html = String::from("<table>\n<tr>");
+    for header in rows.next().unwrap_or_default() {
+        _ = write!(html, "<th>{}</th>", escape(&header));
+    }
+    html.push_str("</tr>\n");
+
+    for row in rows {
+        html.push_str("<tr>");
+        for field in row {
+            _ = write!(html, "<td>{}</td>", escape(field.trim()));
+        }
+        html.push_str("</tr>\n");
+    }
+
+    html + "</table>\n"
+}
+
+/// Share of the replies of each dataset in each RTT range, separated by [`BINS`]. Sketches are
+/// counted by the lowest RTT of each bucket.
+fn draw_histograms<DB: DrawingBackend>(
+    root: DrawingArea<DB, plotters::coord::Shift>,
+    datasets: &[Dataset],
+) -> Result<(), DrawingAreaErrorKind<DB::ErrorType>> {
+    let label = |i: usize| match i {
+        0 => format!("<{} ms", BINS[0]),
+        i if i == BINS.len() => format!(">={} ms", BINS[i - 1]),
+        i => format!("{}-{} ms", BINS[i - 1], BINS[i]),
+    };
+
+    root.fill(&WHITE)?;
+
+    for (i, (set, area)) in datasets
+        .iter()
+        .zip(root.split_evenly((datasets.len(), 1)))
+        .enumerate()
+    {
+        let mut counts = [0usize; BINS.len() + 1];
+        let replies = set
+            .pings
+            .iter()
+            .filter_map(|&(_, rtt)| rtt)
+            .map(|rtt| (rtt, 1));
+        let sketched = set
+            .sketch
+            .buckets
+            .iter()
+            .map(|(&rtt, &n)| (rtt, n as usize));
+        for (rtt, n) in replies.chain(sketched) {
+            let ms = rtt as f64 / 1000.0;
+            counts[BINS.iter().take_while(|&&b| ms >= b).count()] += n;
+        }
+        let replies = counts.iter().sum::<usize>().max(1) as f64;
+
+        let mut chart = ChartBuilder::on(&area)
+            .caption(set.title(), ("sans-serif", 20))
+            .margin(10)
+            .x_label_area_size(30)
+            .y_label_area_size(70)
+            .build_cartesian_2d((0..BINS.len()).into_segmented(), 0.0..100.0)?;
+
+        chart
+            .configure_mesh()
+            .disable_x_mesh()
+            .x_labels(BINS.len() + 1)
+            .x_label_formatter(&|bin| match bin {
+                SegmentValue::CenterOf(i) => label(*i),
+                _ => String::new(),
+            })
+            .y_label_formatter(&|share| format!("{share:.0} %"))
+            .label_style(("sans-serif", 14))
+            .draw()?;
+
+        let color = Palette99::pick(i);
+        chart.draw_series(
+            Histogram::vertical(&chart)
+                .style(color.filled())
+                .margin(10)
+                .data((0..).zip(counts.map(|c| c as f64 / replies * 100.0))),
+        )?;
+    }
+
+    root.present()
+}
+
+/// Share of lost pings over time, in up to [`LOSS_SLOTS`] slots.
+fn draw_loss<DB: DrawingBackend>(
+    root: DrawingArea<DB, plotters::coord::Shift>,
+    datasets: &[Dataset],
+    times: Range<NaiveDateTime>,
+) -> Result<(), DrawingAreaErrorKind<DB::ErrorType>> {
+    let span = times.end - times.start;
+    let pings = datasets.iter().map(|set| set.pings.len()).max();
+    let slots = pings.unwrap_or_default().clamp(1, LOSS_SLOTS);
+    let slot = span / slots as i32;
+
+    root.fill(&WHITE)?;
+
+    for (set, area) in datasets.iter().zip(root.split_evenly((datasets.len(), 1))) {
+        let mut counts = vec![(0usize, 0usize); slots];
+        for &(time, rtt) in &set.pings {
+            let idx = ((time - times.start).num_microseconds().unwrap_or_default()
+                / slot.num_microseconds().unwrap_or(1).max(1)) as usize;
+            let (pings, lost) = &mut counts[idx.min(slots - 1)];
+            *pings += 1;
+            *lost += rtt.is_none() as usize;
+        }
+
+        let mut chart = ChartBuilder::on(&area)
+            .caption(set.title(), ("sans-serif", 20))
+            .margin(10)
+            .x_label_area_size(30)
+            .y_label_area_size(70)
+            .build_cartesian_2d(RangedDateTime::from(times.clone()), 0.0..100.0)?;
+
+        chart
+            .configure_mesh()
+            .disable_x_mesh()
+            .x_labels(16)
+            .x_label_formatter(&|t| concise_label(t, span))
+            .y_label_formatter(&|share| format!("{share:.0} %"))
+            .label_style(("sans-serif", 14))
+            .draw()?;
+
+        let bars = counts.iter().enumerate().filter(|(_, (_, lost))| *lost > 0);
+        chart.draw_series(bars.map(|(i, &(pings, lost))| {
+            let start = times.start + slot * i as i32;
+            let share = lost as f64 / pings as f64 * 100.0;
+            Rectangle::new([(start, 0.0), (start + slot, share)], RED.mix(0.7).filled())
+        }))?;
+    }
+
+    root.present()
+}
+
+/// Draws a chart of `height` px per dataset as SVG.
+fn chart<F>(datasets: &[Dataset], height: u32, draw: F) -> Result<String, String>
+where
+    F: FnOnce(DrawingArea<SVGBackend, plotters::coord::Shift>) -> Result<(), String>,
+{
+    let size = (CHART_WIDTH, height * datasets.len() as u32);
+    let mut svg = String::new();
+    draw(SVGBackend::with_string(&mut svg, size).into_drawing_area())?;
+
+    Ok(svg)
+}
+
+/// Renders the report as a single HTML page with the summary, charts and the report itself.
+fn render(report: &Report, percentiles: &[f64]) -> Result<String, String> {
+    let mut html = String::new();
+    let title = format!("Ping report of {}", report.start_time);
+    let duration = match report.duration {
+        0 => "until interrupted".into(),
+        d => format!("for {d} s"),
+    };
+
+    _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n\
+         <style>{STYLE}</style>\n</head>\n<body>\n<h1>{title}</h1>\n\
+         <p>Pinged {} target(s) every {} ms {duration}{}.</p>\n",
+        report.targets.len(),
+        report.interval,
+        if report.interrupted {
+            ", stopped early"
+        } else {
+            ""
+        },
+    );
+
+    html.push_str("<h2>Summary</h2>\n");
+    let stats: Vec<_> = report
+        .targets
+        .iter()
+        .map(|t| TargetStats::new(t, percentiles))
+        .collect();
+    html.push_str(&cells_table(stats_cells(&stats, percentiles, |t| {
+        &t.rtt.percentiles
+    })));
+
+    if report
+        .targets
+        .iter()
+        .any(|t| t.pings.iter().any(|p| p.rtt().is_none()))
+    {
+        let outage_stats: Vec<_> = report
+            .targets
+            .iter()
+            .enumerate()
+            .map(|(i, t)| OutageStats::new(i, t))
+            .collect();
+        html.push_str("<h2>Outages</h2>\n<p>Consecutive lost pings of each target.</p>\n");
+        html.push_str(&table(&outage_stats));
+    }
+
+    if !report.shared_outages.is_empty() {
+        let shared_stats: Vec<_> = report
+            .shared_outages
+            .iter()
+            .map(|o| SharedOutageStats::new(report, o))
+            .collect();
+        html.push_str(
+            "<h3>Shared outages</h3>\n<p>Outages of several targets at once, most likely caused \
+             near the nearest one.</p>\n",
+        );
+        html.push_str(&table(&shared_stats));
+    }
+
+    for (t, window_stats) in window_stats(report, percentiles) {
+        _ = writeln!(
+            html,
+            "<h3>{} {} over time</h3>",
+            escape(&t.ip),
+            t.probe_name()
+        );
+        html.push_str(&cells_table(stats_cells(&window_stats, percentiles, |w| {
+            &w.percentiles
+        })));
+    }
+
+    for t in report.targets.iter().filter(|t| !t.hops.is_empty()) {
+        let hop_stats: Vec<_> = t
+            .hops
+            .iter()
+            .map(|h| HopStats::new(h, percentiles))
+            .collect();
+        _ = writeln!(html, "<h3>Route to {}</h3>", escape(&t.ip));
+        html.push_str(&cells_table(stats_cells(&hop_stats, percentiles, |h| {
+            &h.rtt.percentiles
+        })));
+    }
+
+    let http_stats: Vec<_> = report.targets.iter().filter_map(HttpStats::new).collect();
+    if !http_stats.is_empty() {
+        html.push_str(
+            "<h2>HTTP timings</h2>\n<p>Median time since the start of each request at which \
+             each phase completed.</p>\n",
+        );
+        html.push_str(&table(&http_stats));
+    }
+
+    let start = start_time(report).ok_or("The report has an invalid start time.")?;
+    let datasets: Vec<Dataset> = report
+        .targets
+        .iter()
+        .map(|t| Dataset {
+            host_name: t.host_name.clone(),
+            probe: probe(t),
+            ips: vec![t.ip.clone()],
+            pings: target_pings(start, t).collect(),
+            sketch: t.sketch.clone().unwrap_or_default(),
+        })
+        .collect();
+
+    // sketches only tell how the RTTs are distributed, not when
+    let times = time_range(&datasets);
+    let error = |e: DrawingAreaErrorKind<_>| format!("Unable to draw the chart: {e}");
+
+    if let Some(times) = &times {
+        html.push_str("<h2>Round trip times</h2>\n");
+        html.push_str(&rtt_chart(&datasets, times.clone())?);
+    }
+
+    if times.is_some() || datasets.iter().any(|set| set.sketch.replies() > 0) {
+        html.push_str("<h2>RTT histogram</h2>\n");
+        html.push_str(&chart(&datasets, CHART_HEIGHT, |root| {
+            draw_histograms(root, &datasets).map_err(error)
+        })?);
+    }
+
+    if let Some(times) = times {
+        html.push_str("<h2>Packet loss</h2>\n");
+        html.push_str(&chart(&datasets, CHART_HEIGHT, |root| {
+            draw_loss(root, &datasets, times).map_err(error)
+        })?);
+    }
+
+    // the complete report for anyone who wants to dig deeper, `</` would end the script
+    let json = serde_json::to_string(report).unwrap().replace("</", "<\\/");
+    _ = write!(
+        html,
+        "<script type=\"application/json\" id=\"report\">{json}</script>\n</body>\n</html>\n"
+    );
+
+    Ok(html)
+}
+
+/// Writes a self-contained HTML page of the report with the summary of the percentiles.
+pub fn write(path: &Path, report: &Report, percentiles: &[f64]) -> Result<(), String> {
+    let html = render(report, percentiles)?;
+    fs::write(path, html).map_err(|e| format!("Unable to write '{}': {e}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::sketch::RttSketch;
+
+    /// A gateway that lost a ping and a web server that answered twice.
+    fn report() -> Report {
+        let json = json!({
+            "version": crate::REPORT_VERSION,
+            "start_time": "2024-03-01T08:00:00+00:00",
+            "duration": 2,
+            "interval": 500,
+            "targets": [
+                {
+                    "host_name": "gateway",
+                    "ip": "10.0.0.1",
+                    "pings": [
+                        { "started_at": 0, "status": "ok", "rtt": 1_800 },
+                        { "started_at": 500_000, "status": "timeout" },
+                        { "started_at": 1_000_000, "status": "ok", "rtt": 2_200 }
+                    ]
+                },
+                {
+                    "host_name": "example.com",
+                    "ip": "93.184.216.34",
+                    "protocol": "http",
+                    "port": 80,
+                    "url": "http://example.com:80/",
+                    "pings": [
+                        {
+                            "started_at": 0,
+                            "status": "ok",
+                            "rtt": 40_000,
+                            "dns": 2_000,
+                            "connect": 12_000,
+                            "ttfb": 38_000,
+                            "total": 40_000,
+                            "http_status": 200
+                        },
+                        {
+                            "started_at": 500_000,
+                            "status": "ok",
+                            "rtt": 60_000,
+                            "dns": 4_000,
+                            "connect": 16_000,
+                            "ttfb": 58_000,
+                            "total": 60_000,
+                            "http_status": 200
+                        }
+                    ]
+                }
+            ]
+        });
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn reports_with_pings_get_tables_and_charts() {
+        let html = render(&report(), &[50.0, 95.0]).unwrap();
+
+        for section in [
+            "<h2>Summary</h2>",
+            "<h2>Outages</h2>",
+            "<h2>HTTP timings</h2>",
+            "<h2>Round trip times</h2>",
+            "<h2>RTT histogram</h2>",
+            "<h2>Packet loss</h2>",
+        ] {
+            assert!(html.contains(section), "missing {section}");
+        }
+        assert_eq!(html.matches("<svg").count(), 3);
+        assert!(html.contains("<th>P50</th><th>P95</th>"));
+
+        // medians of the phases of both responses
+        assert!(html.contains(
+            "<tr><td>http://example.com:80/</td><td>2</td><td>3.00 ms</td><td>14.0 ms</td>\
+             <td>-</td><td>48.0 ms</td><td>50.0 ms</td></tr>"
+        ));
+        assert!(html.contains("<script type=\"application/json\" id=\"report\">{"));
+    }
+
+    #[test]
+    fn sketch_only_reports_get_tables_and_a_histogram() {
+        let mut report = report();
+        for t in &mut report.targets {
+            let mut sketch = RttSketch::default();
+            t.pings.iter().for_each(|p| sketch.record(p.rtt()));
+            t.sketch = Some(sketch);
+            t.pings.clear();
+        }
+        let html = render(&report, &[50.0]).unwrap();
+
+        assert!(html.contains("<h2>Summary</h2>"));
+        assert!(html.contains("<td>10.0.0.1</td><td>gateway</td><td>ICMP</td><td>3</td>"));
+        // the times of the pings are gone, only their distribution is left
+        assert!(html.contains("<h2>RTT histogram</h2>"));
+        assert_eq!(html.matches("<svg").count(), 1);
+        assert!(!html.contains("<h2>Round trip times</h2>"));
+        assert!(!html.contains("<h2>Outages</h2>"));
+        assert!(!html.contains("<h2>HTTP timings</h2>"));
+    }
+}
@@ -1,88 +1,524 @@
+mod analyze;
+mod correlate;
+mod dashboard;
+mod html;
+mod output;
+mod pinger;
+mod plot;
+mod reflect;
+mod serve;
+mod sketch;
+mod stats;
+mod thresholds;
+mod trace;
+
 use std::{
+    fmt,
     net::IpAddr,
     path::PathBuf,
+    process,
     time::{Duration, Instant},
 };
 
-use async_std::{fs::write, task};
-use clap::Parser;
+use analyze::AnalyzeArgs;
+use async_std::{
+    channel::{self, Receiver},
+    future::timeout,
+};
+use clap::{Parser, Subcommand};
+use correlate::SharedOutage;
+use dashboard::Dashboard;
 use dns_lookup::{lookup_addr, lookup_host};
-use serde::Serialize;
-use tabled::{Style, Table, Tabled};
-use winping::{AsyncPinger, Buffer};
+use output::{Format, InfluxSink, PingLog, ReportWriter};
+use pinger::{
+    record_type_name, Details, DnsPinger, DnsQuery, HttpPinger, HttpTiming, HttpUrl, IcmpPinger,
+    PingError, Pinger, TcpPinger, UdpPinger,
+};
+use plot::PlotArgs;
+use reflect::ReflectArgs;
+use serde::{Deserialize, Serialize};
+use serve::{Metrics, ServeArgs};
+use sketch::RttSketch;
+use stats::{Stats, Window};
+use tabled::{builder::Builder, Style, Table, Tabled};
+use trace::{HopReport, TraceArgs};
 
-#[derive(Serialize)]
+/// Version of the report format, increased whenever existing fields change their meaning.
+///
+/// 1. times in ms, the version field didn't exist yet
+/// 2. times in µs
+const REPORT_VERSION: u32 = 2;
+/// Exit status of a run that failed, e.g. because the report couldn't be written.
+const EXIT_FAILURE: i32 = 1;
+/// Exit status of a run in which a target violated --max-loss, --max-p95 or --max-jitter.
+const EXIT_VIOLATION: i32 = 3;
+
+#[derive(Serialize, Deserialize)]
+#[serde(from = "ReportFile")]
 struct Report {
+    version: u32,
+    start_time: String,
+    /// Planned run time in s, 0 if the run was meant to go on until interrupted.
+    duration: u32,
+    interval: u32,
+    /// Whether the run was stopped by a signal, i.e. before its planned end.
+    #[serde(skip_serializing_if = "is_false")]
+    interrupted: bool,
+    targets: Vec<TargetReport>,
+    /// Outages several targets had at the same time.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    shared_outages: Vec<SharedOutage>,
+}
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+impl Report {
+    fn new(args: &PingArgs, schedule: &Schedule, mut targets: Vec<TargetReport>) -> Self {
+        for t in &mut targets {
+            let window = args.window.map(|w| w.as_micros() as u64);
+            t.stats = Some(Stats::new(t, &args.percentiles, window));
+        }
+        let shared_outages = correlate::correlate(args.interval, &mut targets);
+
+        Self {
+            version: REPORT_VERSION,
+            start_time: schedule.start_date.to_rfc3339(),
+            duration: args.duration(),
+            interval: args.interval,
+            interrupted: schedule.interrupted(),
+            targets,
+            shared_outages,
+        }
+    }
+}
+
+/// A [`Report`] as written by any version, converted to the current format when read.
+#[derive(Deserialize)]
+struct ReportFile {
+    #[serde(default = "first_version")]
+    version: u32,
     start_time: String,
     duration: u32,
     interval: u32,
+    #[serde(default)]
+    interrupted: bool,
     targets: Vec<TargetReport>,
+    #[serde(default)]
+    shared_outages: Vec<SharedOutage>,
+}
+
+fn first_version() -> u32 {
+    1
+}
+
+impl From<ReportFile> for Report {
+    fn from(file: ReportFile) -> Self {
+        let mut targets = file.targets;
+
+        if file.version < 2 {
+            let pings = targets.iter_mut().flat_map(|t| {
+                t.hops
+                    .iter_mut()
+                    .map(|h| &mut h.pings)
+                    .chain([&mut t.pings])
+            });
+            pings.flatten().for_each(PingResult::millis_to_micros);
+        }
+
+        Report {
+            version: REPORT_VERSION,
+            start_time: file.start_time,
+            duration: file.duration,
+            interval: file.interval,
+            interrupted: file.interrupted,
+            targets,
+            shared_outages: file.shared_outages,
+        }
+    }
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 struct TargetReport {
+    /// Position of the target on the command line, which tells the pings of repeated targets
+    /// apart while the run goes on.
+    #[serde(skip)]
+    idx: usize,
     host_name: String,
     ip: String,
+    /// Older reports only contained ICMP targets.
+    #[serde(default = "icmp")]
+    protocol: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    port: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    query: Option<String>,
     pings: Vec<PingResult>,
+    /// The RTTs of all pings, only if no pings were kept, see --sketch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sketch: Option<RttSketch>,
+    /// Computed at the end of the run, older reports don't have them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stats: Option<Stats>,
+    /// Routers on the way to the target, only filled by `trace`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    hops: Vec<HopReport>,
+}
+
+fn icmp() -> String {
+    Probe::Icmp.protocol().into()
+}
+
+impl TargetReport {
+    fn new(idx: usize, target: &Target) -> Self {
+        Self {
+            idx,
+            host_name: target.host.clone().unwrap_or_default(),
+            ip: target.ip.to_string(),
+            protocol: target.probe.protocol().into(),
+            port: target.probe.port(),
+            url: target.probe.url(),
+            query: target.probe.query(),
+            pings: Vec::new(),
+            sketch: None,
+            stats: None,
+            hops: Vec::new(),
+        }
+    }
+
+    /// The protocol and port, e.g. `TCP 443`.
+    fn probe_name(&self) -> String {
+        match self.port {
+            Some(port) => format!("{} {port}", self.protocol.to_uppercase()),
+            None => self.protocol.to_uppercase(),
+        }
+    }
+
+    /// `host (ip) probe` or only the IP and probe if the host has no name.
+    fn title(&self) -> String {
+        match self.host_name.as_str() {
+            "" => format!("{} {}", self.ip, self.probe_name()),
+            host => format!("{host} ({}) {}", self.ip, self.probe_name()),
+        }
+    }
 }
 
-#[derive(Serialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(from = "AnyPingResult")]
 struct PingResult {
-    started_at: u32,
-    rtt: u32,
+    /// Time since the start of the run in µs.
+    started_at: u64,
+    #[serde(flatten)]
+    status: Status,
+    #[serde(flatten)]
+    details: Details,
+}
+
+impl PingResult {
+    /// The round trip time in µs if a reply arrived.
+    fn rtt(&self) -> Option<u32> {
+        match self.status {
+            Status::Ok { rtt } => Some(rtt),
+            _ => None,
+        }
+    }
+
+    /// Converts the times of a version 1 report.
+    fn millis_to_micros(&mut self) {
+        let to_micros = |ms: &mut u32| *ms = ms.saturating_mul(1000);
+
+        self.started_at *= 1000;
+        if let Status::Ok { rtt } = &mut self.status {
+            to_micros(rtt);
+        }
+        if let Some(http) = &mut self.details.http {
+            [
+                &mut http.dns,
+                &mut http.connect,
+                &mut http.tls,
+                &mut http.ttfb,
+                &mut http.total,
+            ]
+            .into_iter()
+            .flatten()
+            .for_each(to_micros);
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+/// Outcome of a ping, see [`PingError`] for the failures.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum Status {
+    Ok {
+        rtt: u32,
+    },
+    Timeout,
+    Unreachable {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        icmp_type: Option<u8>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        icmp_code: Option<u8>,
+    },
+    TtlExpired {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        icmp_type: Option<u8>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        icmp_code: Option<u8>,
+    },
+    SendFailed {
+        error: String,
+    },
+    Error {
+        error: String,
+    },
+    /// A failed ping read from a report of an older version, which didn't record why it failed.
+    Failed,
+}
+
+impl Status {
+    /// The value of the `status` field.
+    fn name(&self) -> &'static str {
+        match self {
+            Status::Ok { .. } => "ok",
+            Status::Timeout => "timeout",
+            Status::Unreachable { .. } => "unreachable",
+            Status::TtlExpired { .. } => "ttl_expired",
+            Status::SendFailed { .. } => "send_failed",
+            Status::Error { .. } => "error",
+            Status::Failed => "failed",
+        }
+    }
+}
+
+impl From<Result<u32, PingError>> for Status {
+    fn from(result: Result<u32, PingError>) -> Self {
+        let e = match result {
+            Ok(rtt) => return Status::Ok { rtt },
+            Err(e) => e,
+        };
+        let icmp_type = e.icmp().map(|icmp| icmp.kind);
+        let icmp_code = e.icmp().map(|icmp| icmp.code);
+
+        match e {
+            PingError::Timeout => Status::Timeout,
+            PingError::Unreachable(_) => Status::Unreachable {
+                icmp_type,
+                icmp_code,
+            },
+            PingError::TtlExpired(_) => Status::TtlExpired {
+                icmp_type,
+                icmp_code,
+            },
+            PingError::SendFailed(error) => Status::SendFailed { error },
+            PingError::Other(error) => Status::Error { error },
+        }
+    }
+}
+
+/// Accepts pings of the current format as well as those of older reports, which stored
+/// `u32::MAX` as `rtt` for failed pings.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AnyPingResult {
+    Current {
+        started_at: u64,
+        #[serde(flatten)]
+        status: Status,
+        #[serde(flatten)]
+        details: Details,
+    },
+    Legacy {
+        started_at: u64,
+        rtt: u32,
+        #[serde(flatten)]
+        details: Details,
+    },
+}
+
+impl From<AnyPingResult> for PingResult {
+    fn from(ping: AnyPingResult) -> Self {
+        match ping {
+            AnyPingResult::Current {
+                started_at,
+                status,
+                details,
+            } => PingResult {
+                started_at,
+                status,
+                details,
+            },
+            AnyPingResult::Legacy {
+                started_at,
+                rtt,
+                details,
+            } => PingResult {
+                started_at,
+                status: match rtt {
+                    u32::MAX => Status::Failed,
+                    rtt => Status::Ok { rtt },
+                },
+                details,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Probe {
+    Icmp,
+    Tcp(u16),
+    Udp(u16),
+    Http(HttpUrl),
+    Dns(DnsQuery),
+}
+
+impl Probe {
+    fn protocol(&self) -> &'static str {
+        match self {
+            Probe::Icmp => "icmp",
+            Probe::Tcp(_) => "tcp",
+            Probe::Udp(_) => "udp",
+            Probe::Http(url) if url.tls => "https",
+            Probe::Http(_) => "http",
+            Probe::Dns(_) => "dns",
+        }
+    }
+
+    fn port(&self) -> Option<u16> {
+        match self {
+            Probe::Icmp => None,
+            Probe::Tcp(port) | Probe::Udp(port) => Some(*port),
+            Probe::Http(url) => Some(url.port),
+            Probe::Dns(query) => Some(query.port),
+        }
+    }
+
+    fn url(&self) -> Option<String> {
+        match self {
+            Probe::Http(url) => Some(url.to_string()),
+            _ => None,
+        }
+    }
+
+    fn query(&self) -> Option<String> {
+        match self {
+            Probe::Dns(query) => Some(format!(
+                "{} {}",
+                query.name,
+                record_type_name(query.record_type)
+            )),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 struct Target {
     ip: IpAddr,
     host: Option<String>,
+    probe: Probe,
 }
 
-impl ToString for Target {
-    fn to_string(&self) -> String {
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self.host.as_deref() {
-            Some(name) => format!("{name} ({})", self.ip),
-            None => self.ip.to_string(),
+            Some(name) => write!(f, "{name} ({})", self.ip)?,
+            None => write!(f, "{}", self.ip)?,
+        }
+        match &self.probe {
+            Probe::Icmp => Ok(()),
+            Probe::Tcp(port) => write!(f, " TCP port {port}"),
+            Probe::Udp(port) => write!(f, " UDP port {port}"),
+            Probe::Http(url) => write!(f, " {url}"),
+            Probe::Dns(_) => write!(f, " DNS query {}", self.probe.query().unwrap_or_default()),
         }
     }
 }
 
 fn resolve_target(s: &str) -> Result<Target, String> {
+    let (host, probe) = if let Some(query) = DnsQuery::parse(s) {
+        let (resolver, query) = query?;
+        (resolver.to_string(), Probe::Dns(query))
+    } else if let Some(url) = HttpUrl::parse(s) {
+        let url = url?;
+        (url.host.clone(), Probe::Http(url))
+    } else if let Some(addr) = s.strip_prefix("udp://") {
+        match split_port(addr) {
+            Some((host, port)) => (host.to_string(), Probe::Udp(port)),
+            None => return Err(format!("'{s}' is missing a port")),
+        }
+    } else {
+        match split_port(s) {
+            Some((host, port)) => (host.to_string(), Probe::Tcp(port)),
+            None => (s.to_string(), Probe::Icmp),
+        }
+    };
+
+    match resolve_host(&host) {
+        Some((ip, host)) => Ok(Target { ip, host, probe }),
+        None => Err(format!("'{s}' is not a valid IP or host name")),
+    }
+}
+
+/// Splits `host:port`, `ip:port` or `[ipv6]:port` into host and port.
+fn split_port(s: &str) -> Option<(&str, u16)> {
+    let (host, port) = s.rsplit_once(':')?;
+    let port = port.parse().ok()?;
+
+    match host.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+        Some(ipv6) => Some((ipv6, port)),
+        // a bare IPv6 address
+        None if host.contains(':') => None,
+        None => Some((host, port)),
+    }
+}
+
+fn resolve_host(s: &str) -> Option<(IpAddr, Option<String>)> {
     if let Ok(ip) = s.parse::<IpAddr>() {
         if let Ok(name) = lookup_addr(&ip) {
-            if let Err(_) = name.parse::<IpAddr>() {
+            if name.parse::<IpAddr>().is_err() {
                 // sometimes the IP is returned as host name
-                return Ok(Target {
-                    ip,
-                    host: Some(name.into()),
-                });
+                return Some((ip, Some(name)));
             }
         }
-        return Ok(Target { ip, host: None });
+        Some((ip, None))
     } else {
-        if let Ok(ips) = lookup_host(&s).as_deref() {
-            if let Some(&ip) = ips.iter().find(|&&ip| ip.is_ipv4()).or(ips.first()) {
-                return Ok(Target {
-                    ip,
-                    host: Some(s.into()),
-                });
-            }
-        }
-        Err(format!("'{s}' is not a valid IP or host name"))
+        let ips = lookup_host(s).ok()?;
+        let &ip = ips.iter().find(|&&ip| ip.is_ipv4()).or(ips.first())?;
+        Some((ip, Some(s.into())))
     }
 }
 
 #[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
+#[clap(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    #[clap(flatten)]
+    ping: PingArgs,
+}
+
+/// Options shared by all modes that ping a list of targets.
+#[derive(clap::Args, Debug)]
+struct PingArgs {
     /// Interval between two pings in ms.
     #[clap(short, long, default_value_t = 500)]
     interval: u32,
 
-    /// Total run time (for how long to ping targets) in s.
+    /// Total run time (for how long to ping targets) in s. 0 pings until interrupted.
     #[clap(short, long, default_value_t = 30)]
     duration: u32,
 
+    /// Pings until interrupted, same as `--duration 0`. Ctrl-C (SIGINT) or SIGTERM stop the run
+    /// and still write the report and summary.
+    #[clap(long, conflicts_with = "duration")]
+    forever: bool,
+
     /// Timeout for each ping.
     #[clap(short, long, default_value_t = 1000)]
     timeout: u32,
@@ -95,6 +531,60 @@ struct Args {
     #[clap(long)]
     out_file: Option<PathBuf>,
 
+    /// Format of the report. Inferred from the extension of --out-file (`.json`, `.ndjson`,
+    /// `.jsonl`, `.csv`, `.sqlite`, `.db` or `.lp`) if not given, JSON otherwise. NDJSON, CSV and
+    /// line protocol are appended to while the pings run. With --out-dir, all runs go into one
+    /// SQLite database.
+    #[clap(long, value_enum)]
+    format: Option<Format>,
+
+    /// Also sends the pings in InfluxDB line protocol to this write endpoint, in batches of up to
+    /// 5000 lines at least every 10 s, e.g. `http://localhost:8086/api/v2/write?bucket=pings`.
+    /// The token in the INFLUX_TOKEN environment variable is sent if set.
+    #[clap(long)]
+    influx_url: Option<String>,
+
+    /// Also writes a self-contained HTML page with the summary, charts and the report to this
+    /// file, for anyone who'd rather not read JSON.
+    #[clap(long)]
+    html_file: Option<PathBuf>,
+
+    /// Percentiles of the RTTs to show in the summary and write to the report, from 0 to 100.
+    /// They are interpolated linearly between the two closest RTTs, like R and NumPy do by
+    /// default.
+    #[clap(
+        long,
+        value_parser = parse_percent,
+        use_value_delimiter = true,
+        default_value = "50,95"
+    )]
+    percentiles: Vec<f64>,
+
+    /// Also splits the pings of each target into consecutive windows of this length, e.g. `60s`,
+    /// `5m` or `1h`, and shows the loss and RTTs of each window in the summary and report.
+    #[clap(long, value_parser = parse_duration, conflicts_with = "sketch")]
+    window: Option<Duration>,
+
+    /// Exits with status 3 after the run if the packet loss of any target is above this share,
+    /// e.g. `1%`.
+    #[clap(long, value_parser = parse_percent)]
+    max_loss: Option<f64>,
+
+    /// Exits with status 3 after the run if the 95th percentile of the RTTs of any target is above
+    /// this, e.g. `80ms`.
+    #[clap(long, value_parser = parse_duration)]
+    max_p95: Option<Duration>,
+
+    /// Exits with status 3 after the run if the jitter of any target is above this, e.g. `10ms`.
+    #[clap(long, value_parser = parse_duration, conflicts_with = "sketch")]
+    max_jitter: Option<Duration>,
+
+    /// Keeps only a histogram of the RTTs of each target instead of every ping, which is written
+    /// to the report in their place. Long runs then need little memory, but jitter and outages
+    /// can't be told from it. Pings written to NDJSON, CSV or line protocol are complete anyway.
+    #[clap(long)]
+    sketch: bool,
+
     /// Prints an introduction to stdout.
     #[clap(long, value_parser, default_value_t = true)]
     display_intro: bool,
@@ -107,126 +597,786 @@ struct Args {
     #[clap(long, value_parser)]
     display_pings: bool,
 
-    /// List of targets to ping. Each target can be an IP or host name.
+    /// Shows a live dashboard instead, with a row per target that is updated in place: a
+    /// sparkline of the latest RTTs, the packet loss and the RTT statistics so far.
+    #[clap(long, conflicts_with = "display-pings")]
+    tui: bool,
+
+    /// List of targets to ping. Each target can be an IP or host name. Targets written as
+    /// `host:port` (or `[ipv6]:port`) measure the TCP handshake time instead of ICMP echoes.
+    /// Targets written as `udp://host:port` send datagrams to a UDP echo service (see `reflect`).
+    /// `http://` and `https://` URLs time the phases of a GET request.
+    /// `dns://resolver[:port][/name[/type]]` measures the response time of a DNS resolver.
+    /// List near targets first, e.g. the gateway before 8.8.8.8, since outages that several
+    /// targets have at the same time are attributed to the nearest of them.
     #[clap(value_parser=resolve_target)]
     ips_or_host_names: Vec<Target>,
 }
 
+impl PingArgs {
+    fn format(&self) -> Format {
+        self.format
+            .or_else(|| self.out_file.as_deref().and_then(Format::from_extension))
+            .unwrap_or(Format::Json)
+    }
+
+    /// The run time in s, 0 to run until interrupted.
+    fn duration(&self) -> u32 {
+        if self.forever {
+            0
+        } else {
+            self.duration
+        }
+    }
+
+    fn has_thresholds(&self) -> bool {
+        self.max_loss.is_some() || self.max_p95.is_some() || self.max_jitter.is_some()
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Runs a UDP echo service that answers the probes of `udp://` targets.
+    Reflect(ReflectArgs),
+    /// Traces the route to each target and collects ping statistics for every hop, like mtr.
+    Trace(TraceArgs),
+    /// Pings the targets until interrupted and serves Prometheus metrics of them on `/metrics`.
+    /// --duration is ignored and only NDJSON, CSV or line protocol reports can be written.
+    Serve(ServeArgs),
+    /// Prints statistics of the JSON reports in a directory per host, per hour of day and for
+    /// the most recent pings.
+    Analyze(AnalyzeArgs),
+    /// Draws the RTTs of each host in JSON reports as an SVG or PNG chart.
+    Plot(PlotArgs),
+}
+
 #[async_std::main]
 async fn main() {
-    let args = Args::parse();
+    let mut args = Args::parse();
 
-    if args.display_intro {
-        display_intro(&args);
+    if let Some(Command::Reflect(reflect_args)) = &args.command {
+        if let Err(e) = reflect::reflect(reflect_args).await {
+            fail(e);
+        }
+        return;
+    }
+
+    if let Some(Command::Analyze(analyze_args)) = &args.command {
+        if let Err(e) = analyze::analyze(analyze_args) {
+            fail(e);
+        }
+        return;
+    }
+
+    if let Some(Command::Plot(plot_args)) = &args.command {
+        if let Err(e) = plot::plot(plot_args) {
+            fail(e);
+        }
+        return;
+    }
+
+    if let Some(Command::Serve(serve_args)) = &mut args.command {
+        serve_args.ping.forever = true;
+    }
+
+    let ping_args = match &args.command {
+        Some(Command::Trace(trace_args)) => &trace_args.ping,
+        Some(Command::Serve(serve_args)) => &serve_args.ping,
+        _ => &args.ping,
+    };
+
+    if ping_args.display_intro {
+        display_intro(ping_args);
+    }
+
+    let serving = matches!(args.command, Some(Command::Serve(_)));
+
+    if let Some(file) = &ping_args.html_file {
+        if serving {
+            fail("Pings aren't kept while serving, so there is nothing to show in HTML.");
+        } else if file.exists() {
+            fail(format!("File '{}' already exists.", file.display()));
+        }
+    }
+
+    if ping_args.tui && matches!(args.command, Some(Command::Trace(_))) {
+        fail("The dashboard only shows pinged targets, not traced ones.");
+    }
+
+    // each target gets a series of its own
+    if serving {
+        if let Some(t) = ping_args
+            .ips_or_host_names
+            .iter()
+            .enumerate()
+            .find(|(i, t)| ping_args.ips_or_host_names[..*i].contains(t))
+            .map(|(_, t)| t)
+        {
+            fail(format!(
+                "'{t}' is given more than once, which would serve the same metrics twice."
+            ));
+        }
+    }
+
+    if serving && ping_args.has_thresholds() {
+        fail("Serving keeps no report to check --max-loss, --max-p95 or --max-jitter against.");
+    }
+
+    if ping_args.sketch && ping_args.format() == Format::Sqlite {
+        fail("SQLite databases hold every ping, which --sketch doesn't keep.");
     }
 
     let mut out_file = None;
 
-    if args.out_dir.is_some() || args.out_file.is_some() {
-        match resolve_out_file(&args) {
+    if ping_args.out_dir.is_some() || ping_args.out_file.is_some() {
+        if serving && !ping_args.format().is_streamed() {
+            fail("Pings aren't kept while serving, use NDJSON, CSV or line protocol to write them to a file.");
+        }
+
+        match resolve_out_file(ping_args) {
             Ok(p) => out_file = Some(p),
-            Err(e) => {
-                println!("{e}");
-                return;
-            }
+            Err(e) => fail(e),
         }
     }
 
-    let report = run(&args).await;
+    let writer = match out_file.map(|p| ReportWriter::open(p, ping_args.format())) {
+        Some(Ok(w)) => Some(w),
+        Some(Err(e)) => fail(e),
+        None => None,
+    };
+
+    let influx = match ping_args.influx_url.as_deref().map(InfluxSink::open) {
+        Some(Ok(sink)) => Some(sink),
+        Some(Err(e)) => fail(e),
+        None => None,
+    };
+
+    let dashboard = ping_args.tui.then(|| Dashboard::new(ping_args));
 
-    if let Some(p) = &out_file {
-        let json = serde_json::to_string_pretty(&report).unwrap();
-        _ = write(p, json).await;
+    let out = Output {
+        display_pings: ping_args.display_pings,
+        log: writer.as_ref().and_then(ReportWriter::log),
+        influx: influx.as_ref(),
+        metrics: None,
+        sketch: ping_args.sketch,
+        dashboard: dashboard.as_ref(),
+    };
+    let logged = out.log.is_some();
+
+    // serving keeps no report
+    let pings = async {
+        match &args.command {
+            Some(Command::Trace(trace_args)) => trace::trace(trace_args, out).await.map(Some),
+            Some(Command::Serve(serve_args)) => serve::serve(serve_args, out).await.map(|_| None),
+            _ => run(ping_args, out).await.map(Some),
+        }
+    };
+    let report = match &dashboard {
+        Some(dashboard) => dashboard.show(pings).await,
+        None => pings.await,
+    };
+
+    if let Some(influx) = influx {
+        influx.finish().await;
+    }
+
+    let report = match report {
+        Ok(Some(r)) => r,
+        Ok(None) => return,
+        Err(e) => fail(e),
+    };
+
+    // the summary is still shown if the report can't be written, which fails the run anyway
+    let mut failed = false;
+
+    if let Some(writer) = writer {
+        if let Err(e) = writer.finish(&report).await {
+            println!("{e}");
+            failed = true;
+        }
+    }
+
+    if let Some(file) = &ping_args.html_file {
+        if let Err(e) = html::write(file, &report, &ping_args.percentiles) {
+            println!("{e}");
+            failed = true;
+        }
     }
 
-    if args.display_summary {
-        display_summary(&report);
+    if ping_args.display_summary {
+        display_summary(&report, &ping_args.percentiles);
+    }
+
+    if ping_args.sketch {
+        println!("Only sketches of the RTTs were kept, so jitter, MOS and outages are left out.");
+    }
+    if logged && report.targets.iter().any(|t| t.url.is_some()) {
+        println!("HTTP timings were only logged, so they are left out of the summary and HTML.");
+    }
+
+    let violations = thresholds::violations(ping_args, &report);
+    for violation in &violations {
+        println!("{violation}");
+    }
+
+    if let Some(status) = exit_status(failed, &violations) {
+        process::exit(status);
+    }
+}
+
+/// The status to exit with after a run, `None` to exit normally. Failing to write the report
+/// takes precedence over violated thresholds.
+fn exit_status(failed: bool, violations: &[String]) -> Option<i32> {
+    if failed {
+        Some(EXIT_FAILURE)
+    } else if !violations.is_empty() {
+        Some(EXIT_VIOLATION)
+    } else {
+        None
     }
 }
 
-fn display_summary(report: &Report) {
-    #[derive(Tabled)]
-    #[tabled(rename_all = "PascalCase")]
-    struct Stats<'s> {
-        #[tabled(rename = "IP")]
-        ip: &'s str,
-        host: &'s str,
-        pings: u32,
-        #[tabled(rename = "Packet Loss")]
-        packet_loss: String,
-        min: String,
-        median: String,
-        mean: String,
-        #[tabled(rename = "5% High")]
-        per95: String,
-        max: String,
-        #[tabled(rename = "Std Dev")]
-        stddev: String,
+/// Prints why the run can't go on and exits with [`EXIT_FAILURE`].
+fn fail(message: impl fmt::Display) -> ! {
+    println!("{message}");
+    process::exit(EXIT_FAILURE)
+}
+
+/// Statistics of the RTTs of a series of pings.
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+struct RttStats {
+    pings: u32,
+    #[tabled(rename = "Packet Loss")]
+    packet_loss: String,
+    min: String,
+    /// The RTT at each percentile, which get columns of their own after the minimum in
+    /// [`stats_table`].
+    #[tabled(skip)]
+    percentiles: Vec<String>,
+    max: String,
+    mean: String,
+    #[tabled(rename = "Std Dev")]
+    stddev: String,
+}
+
+impl RttStats {
+    fn new(pings: &[PingResult], percentiles: &[f64]) -> Self {
+        let in_time = pings.iter().filter_map(PingResult::rtt).collect();
+        Self::from_rtts(pings.len(), in_time, percentiles)
     }
 
-    fn compute_stats(t: &TargetReport) -> Stats {
-        let mut pings: Vec<u32> = t.pings.iter().map(|p| p.rtt).collect();
-        pings.sort();
-        pings.push(u32::MAX); // add a fake timeout entry so we can use partition_point
-        let idx = pings.partition_point(|&p| p < u32::MAX);
-        let in_time = &pings[..idx];
-        let ping_count = (pings.len() - 1) as u32;
+    /// Computes the statistics of `ping_count` pings, of which those in `in_time` got a reply.
+    fn from_rtts(ping_count: usize, mut in_time: Vec<u32>, percentiles: &[f64]) -> Self {
+        in_time.sort();
+        let ping_count = ping_count as u32;
 
         if in_time.is_empty() {
-            Stats {
-                ip: &t.ip,
-                host: &t.host_name,
+            RttStats {
                 pings: ping_count,
                 packet_loss: "100.00 %".into(),
                 min: "-".into(),
-                median: "-".into(),
-                mean: "-".into(),
-                per95: "-".into(),
+                percentiles: vec!["-".into(); percentiles.len()],
                 max: "-".into(),
+                mean: "-".into(),
                 stddev: "-".into(),
             }
         } else {
             let mean = in_time.iter().fold(0f64, |t, x| t + (*x as f64)) / (in_time.len() as f64);
-            let sqr_err = in_time.iter().fold(0f64, |t, x| t + ((*x as f64 - mean).powi(2)));
+            let sqr_err = in_time
+                .iter()
+                .fold(0f64, |t, x| t + ((*x as f64 - mean).powi(2)));
             let stddev = (sqr_err / in_time.len() as f64).sqrt();
+            let loss = 1.0 - in_time.len() as f32 / ping_count as f32;
 
-            Stats {
-                ip: &t.ip,
-                host: &t.host_name,
+            RttStats {
                 pings: ping_count,
-                packet_loss: format!("{:>6.2} %", 1.0 - in_time.len() as f32 / ping_count as f32),
-                min: format!("{:>4} ms", in_time[0]),
-                median: format!("{:>4} ms", in_time[in_time.len() / 2]),
-                mean: format!("{:>4.0} ms", mean),
-                per95: format!("{:>4} ms", in_time[(in_time.len() as f64 * 0.95) as usize]),
-                max: format!("{:>4} ms", in_time[in_time.len() - 1]),
-                stddev: format!("{:>4.0} ms", stddev)
+                packet_loss: format!("{:>6.2} %", loss * 100.0),
+                min: format_micros(in_time[0] as f64),
+                percentiles: percentiles
+                    .iter()
+                    .map(|&p| format_micros(stats::percentile(&in_time, p).unwrap_or_default()))
+                    .collect(),
+                max: format_micros(in_time[in_time.len() - 1] as f64),
+                mean: format_micros(mean),
+                stddev: format_micros(stddev),
             }
         }
     }
 
-    let stats: Vec<_> = report.targets.iter().map(compute_stats).collect();
-    let table = Table::new(&stats).with(Style::modern());
+    /// The same statistics from a sketch, whose percentiles are a little less accurate.
+    fn from_sketch(sketch: &RttSketch, percentiles: &[f64]) -> Self {
+        let time = |us: Option<f64>| us.map_or_else(|| "-".into(), format_micros);
+        let loss = 1.0 - sketch.replies() as f64 / sketch.pings.max(1) as f64;
+
+        RttStats {
+            pings: sketch.pings as u32,
+            packet_loss: format!("{:>6.2} %", loss * 100.0),
+            min: time(sketch.min.map(f64::from)),
+            percentiles: percentiles
+                .iter()
+                .map(|&p| time(sketch.percentile(p)))
+                .collect(),
+            max: time(sketch.max.map(f64::from)),
+            mean: time(sketch.mean()),
+            stddev: time(sketch.stddev()),
+        }
+    }
+}
+
+/// The name of the column of a percentile, e.g. `P99.9`.
+fn percentile_name(p: f64) -> String {
+    format!("P{p}")
+}
+
+/// The cells of a table of rows with RTT statistics, headers first, with a column for each
+/// percentile after the minimum. They can't be derived since the percentiles are only known at
+/// runtime, `row_percentiles` returns those of a row.
+fn stats_cells<T: Tabled>(
+    rows: &[T],
+    percentiles: &[f64],
+    row_percentiles: impl Fn(&T) -> &[String],
+) -> Vec<Vec<String>> {
+    let mut headers = T::headers();
+    let at = headers
+        .iter()
+        .position(|h| h == "Min")
+        .map_or(headers.len(), |min| min + 1);
+    headers.splice(at..at, percentiles.iter().map(|&p| percentile_name(p)));
+
+    let mut cells = vec![headers];
+    for row in rows {
+        let mut fields = row.fields();
+        fields.splice(at..at, row_percentiles(row).iter().cloned());
+        cells.push(fields);
+    }
+
+    cells
+}
+
+fn stats_table<T: Tabled>(
+    rows: &[T],
+    percentiles: &[f64],
+    row_percentiles: impl Fn(&T) -> &[String],
+) -> Table {
+    Builder::from(stats_cells(rows, percentiles, row_percentiles))
+        .build()
+        .with(Style::modern())
+}
+
+/// Parses a percentile or packet loss from 0 to 100, with or without `%`.
+fn parse_percent(s: &str) -> Result<f64, String> {
+    match s.trim().trim_end_matches('%').trim_end().parse::<f64>() {
+        Ok(p) if (0.0..=100.0).contains(&p) => Ok(p),
+        _ => Err(format!("'{s}' is not a percentage from 0 to 100")),
+    }
+}
+
+/// Parses a duration with a unit, `ms`, `s`, `m` or `h`, e.g. `500ms` or `1.5h`.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let scale = match unit.trim() {
+        "ms" => 0.001,
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return Err(format!("'{s}' needs a unit: ms, s, m or h")),
+    };
+
+    match value.trim().parse::<f64>() {
+        Ok(v) if v > 0.0 && v.is_finite() => Ok(Duration::from_secs_f64(v * scale)),
+        _ => Err(format!("'{s}' is not a positive duration")),
+    }
+}
+
+/// A row of the summary table.
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+struct TargetStats<'s> {
+    #[tabled(rename = "IP")]
+    ip: &'s str,
+    host: &'s str,
+    probe: String,
+    #[tabled(inline)]
+    rtt: RttStats,
+    jitter: String,
+    #[tabled(rename = "Mean Δ")]
+    mean_delta: String,
+    #[tabled(rename = "R")]
+    r_factor: String,
+    #[tabled(rename = "MOS")]
+    mos: String,
+}
+
+impl<'s> TargetStats<'s> {
+    fn new(t: &'s TargetReport, percentiles: &[f64]) -> Self {
+        let stats = t
+            .stats
+            .clone()
+            .unwrap_or_else(|| Stats::new(t, percentiles, None));
+        let time = |us: Option<f64>| us.map_or_else(|| "-".into(), format_micros);
+        let score = |s: Option<f64>| s.map_or_else(|| "-".into(), |s| format!("{s:.1}"));
+
+        // the percentiles are those of the report, so that both always agree
+        let mut rtt = match &t.sketch {
+            Some(sketch) => RttStats::from_sketch(sketch, &[]),
+            None => RttStats::new(&t.pings, &[]),
+        };
+        rtt.percentiles = percentiles
+            .iter()
+            .map(|&p| time(stats.percentile(p)))
+            .collect();
+
+        Self {
+            ip: &t.ip,
+            host: &t.host_name,
+            probe: t.probe_name(),
+            rtt,
+            jitter: time(stats.jitter),
+            mean_delta: time(stats.mean_delta),
+            r_factor: score(stats.r_factor),
+            mos: match stats.mos {
+                Some(mos) => format!("{mos:.2}"),
+                None => "-".into(),
+            },
+        }
+    }
+}
+
+/// A row of the table of the hops to a traced target.
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+struct HopStats<'s> {
+    hop: u8,
+    #[tabled(rename = "IP")]
+    ip: &'s str,
+    host: &'s str,
+    #[tabled(inline)]
+    rtt: RttStats,
+}
+
+impl<'s> HopStats<'s> {
+    fn new(hop: &'s HopReport, percentiles: &[f64]) -> Self {
+        Self {
+            hop: hop.ttl,
+            ip: hop.ip.as_deref().unwrap_or("???"),
+            host: &hop.host_name,
+            rtt: match &hop.sketch {
+                Some(sketch) => RttStats::from_sketch(sketch, percentiles),
+                None => RttStats::new(&hop.pings, percentiles),
+            },
+        }
+    }
+}
+
+/// Upper bounds of the outage lengths in lost pings that are counted together in the summary.
+const BURSTS: [usize; 4] = [1, 2, 5, 10];
+
+/// A row of the table of the outages of a target.
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+struct OutageStats<'s> {
+    #[tabled(rename = "IP")]
+    ip: &'s str,
+    host: &'s str,
+    probe: String,
+    outages: usize,
+    /// Outages attributed to a nearer target.
+    shared: usize,
+    longest: String,
+    #[tabled(rename = "1 Lost")]
+    one: usize,
+    #[tabled(rename = "2 Lost")]
+    two: usize,
+    #[tabled(rename = "3-5 Lost")]
+    five: usize,
+    #[tabled(rename = "6-10 Lost")]
+    ten: usize,
+    #[tabled(rename = ">10 Lost")]
+    more: usize,
+}
+
+impl<'s> OutageStats<'s> {
+    /// The outages of the target at index `idx` of the report.
+    fn new(idx: usize, t: &'s TargetReport) -> Self {
+        let stats = t.stats.clone().unwrap_or_else(|| Stats::new(t, &[], None));
+        let mut bursts = [0; BURSTS.len() + 1];
+        for (&lost, &count) in &stats.loss_bursts {
+            bursts[BURSTS.iter().take_while(|&&b| lost > b).count()] += count;
+        }
+        let [one, two, five, ten, more] = bursts;
+
+        Self {
+            ip: &t.ip,
+            host: &t.host_name,
+            probe: t.probe_name(),
+            outages: stats.outages.len(),
+            shared: stats
+                .outages
+                .iter()
+                .filter(|o| o.cause.is_some_and(|c| c != idx))
+                .count(),
+            longest: match stats.longest_outage() {
+                Some(o) => format!("{} ({} lost)", format_micros(o.duration as f64), o.lost),
+                None => "-".into(),
+            },
+            one,
+            two,
+            five,
+            ten,
+            more,
+        }
+    }
+}
+
+/// A row of the table of the outages that several targets had at the same time.
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+struct SharedOutageStats {
+    start: String,
+    duration: String,
+    targets: String,
+    #[tabled(rename = "Nearest Target")]
+    cause: String,
+}
+
+impl SharedOutageStats {
+    fn new(report: &Report, outage: &SharedOutage) -> Self {
+        let name = |idx: usize| match report.targets.get(idx) {
+            Some(t) if t.host_name.is_empty() => format!("{} {}", t.ip, t.probe_name()),
+            Some(t) => format!("{} {}", t.host_name, t.probe_name()),
+            None => "?".into(),
+        };
+        Self {
+            start: wall_clock(report, outage.start),
+            duration: format_micros(outage.duration as f64),
+            targets: outage
+                .targets
+                .iter()
+                .map(|&t| name(t))
+                .collect::<Vec<_>>()
+                .join(", "),
+            cause: name(outage.cause),
+        }
+    }
+}
+
+/// The wall clock time of `started_at` µs into the run, or the seconds since its start if the
+/// report has no valid start time.
+fn wall_clock(report: &Report, started_at: u64) -> String {
+    match analyze::start_time(report) {
+        Some(time) => {
+            let time = time + chrono::Duration::microseconds(started_at as i64);
+            time.format("%F %T").to_string()
+        }
+        None => format!("{} s", started_at / 1_000_000),
+    }
+}
+
+/// A row of the table of the windows of a target.
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+struct WindowStats {
+    from: String,
+    to: String,
+    pings: usize,
+    #[tabled(rename = "Packet Loss")]
+    packet_loss: String,
+    min: String,
+    /// The RTT at each percentile, see [`stats_cells`].
+    #[tabled(skip)]
+    percentiles: Vec<String>,
+    max: String,
+}
+
+impl WindowStats {
+    fn new(report: &Report, window: &Window, percentiles: &[f64]) -> Self {
+        let rtt = |rtt: Option<f64>| match rtt {
+            Some(rtt) => format_micros(rtt),
+            None => "-".into(),
+        };
+
+        Self {
+            from: wall_clock(report, window.start),
+            to: wall_clock(report, window.end),
+            pings: window.pings,
+            packet_loss: format!("{:.2} %", window.lost as f64 / window.pings as f64 * 100.0),
+            min: rtt(window.min.map(f64::from)),
+            percentiles: percentiles
+                .iter()
+                .map(|&p| rtt(window.percentile(p)))
+                .collect(),
+            max: rtt(window.max.map(f64::from)),
+        }
+    }
+}
+
+/// The targets that were split into windows with the rows of their tables.
+fn window_stats<'r>(
+    report: &'r Report,
+    percentiles: &[f64],
+) -> Vec<(&'r TargetReport, Vec<WindowStats>)> {
+    report
+        .targets
+        .iter()
+        .filter_map(|t| {
+            let windows = &t.stats.as_ref()?.windows;
+            let rows = windows
+                .iter()
+                .map(|w| WindowStats::new(report, w, percentiles));
+            (!windows.is_empty()).then(|| (t, rows.collect()))
+        })
+        .collect()
+}
+
+/// A row of the table of the HTTP timings of a target.
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+struct HttpStats<'s> {
+    #[tabled(rename = "URL")]
+    url: &'s str,
+    responses: usize,
+    #[tabled(rename = "DNS")]
+    dns: String,
+    connect: String,
+    #[tabled(rename = "TLS")]
+    tls: String,
+    #[tabled(rename = "TTFB")]
+    ttfb: String,
+    total: String,
+}
+
+impl<'s> HttpStats<'s> {
+    /// Median time since the start of the request at which each phase completed, `None` if the
+    /// target isn't an HTTP one or its timings weren't kept.
+    fn new(t: &'s TargetReport) -> Option<Self> {
+        let url = t.url.as_deref()?;
+        // logged runs keep no timings
+        if t.pings.iter().all(|p| p.details.http.is_none()) {
+            return None;
+        }
+        let timings: Vec<&HttpTiming> = t
+            .pings
+            .iter()
+            .filter(|p| p.rtt().is_some())
+            .filter_map(|p| p.details.http.as_ref())
+            .collect();
+
+        let median = |phase: fn(&HttpTiming) -> Option<u32>| {
+            let mut times: Vec<u32> = timings.iter().filter_map(|&t| phase(t)).collect();
+            times.sort();
+            match stats::percentile(&times, 50.0) {
+                Some(t) => format_micros(t),
+                None => "-".into(),
+            }
+        };
+
+        Some(HttpStats {
+            url,
+            responses: timings.len(),
+            dns: median(|t| t.dns),
+            connect: median(|t| t.connect),
+            tls: median(|t| t.tls),
+            ttfb: median(|t| t.ttfb),
+            total: median(|t| t.total),
+        })
+    }
+}
+
+fn display_summary(report: &Report, percentiles: &[f64]) {
+    for t in report.targets.iter().filter(|t| !t.hops.is_empty()) {
+        let hop_stats: Vec<_> = t
+            .hops
+            .iter()
+            .map(|h| HopStats::new(h, percentiles))
+            .collect();
+        let table = stats_table(&hop_stats, percentiles, |h| &h.rtt.percentiles);
+        println!("Route to {}:\n{table}", t.ip);
+    }
+
+    let stats: Vec<_> = report
+        .targets
+        .iter()
+        .map(|t| TargetStats::new(t, percentiles))
+        .collect();
+    let table = stats_table(&stats, percentiles, |t| &t.rtt.percentiles);
 
     println!("{table}");
+
+    if report
+        .targets
+        .iter()
+        .any(|t| t.pings.iter().any(|p| p.rtt().is_none()))
+    {
+        let outage_stats: Vec<_> = report
+            .targets
+            .iter()
+            .enumerate()
+            .map(|(i, t)| OutageStats::new(i, t))
+            .collect();
+        let table = Table::new(&outage_stats).with(Style::modern());
+        println!("Outages, i.e. consecutive lost pings:\n{table}");
+    }
+
+    if !report.shared_outages.is_empty() {
+        let shared_stats: Vec<_> = report
+            .shared_outages
+            .iter()
+            .map(|o| SharedOutageStats::new(report, o))
+            .collect();
+        let table = Table::new(&shared_stats).with(Style::modern());
+        println!(
+            "Outages of several targets at once, most likely caused near the nearest one:\n{table}"
+        );
+    }
+
+    for (t, window_stats) in window_stats(report, percentiles) {
+        let table = stats_table(&window_stats, percentiles, |w| &w.percentiles);
+        println!("{} {} over time:\n{table}", t.ip, t.probe_name());
+    }
+
+    let http_stats: Vec<_> = report.targets.iter().filter_map(HttpStats::new).collect();
+
+    if !http_stats.is_empty() {
+        let table = Table::new(&http_stats).with(Style::modern());
+        println!("Median HTTP timings since the start of each request:\n{table}");
+    }
+}
+
+/// Formats a duration in µs with a unit that suits its magnitude.
+fn format_micros(us: f64) -> String {
+    if us < 1_000.0 {
+        format!("{us:>6.0} µs")
+    } else if us < 10_000.0 {
+        format!("{:>6.2} ms", us / 1_000.0)
+    } else if us < 1_000_000.0 {
+        format!("{:>6.1} ms", us / 1_000.0)
+    } else {
+        format!("{:>6.2} s", us / 1_000_000.0)
+    }
 }
 
-fn display_intro(args: &Args) {
+fn display_intro(args: &PingArgs) {
+    let until = match args.duration() {
+        0 => "until interrupted".into(),
+        d => format!("for the next {d}s"),
+    };
     println!(
-        "Pinging {} target(s) every {}ms for the next {}s:",
+        "Pinging {} target(s) every {}ms {until}:",
         args.ips_or_host_names.len(),
         args.interval,
-        args.duration
     );
 
     for target in &args.ips_or_host_names {
-        println!("    {}", target.to_string())
+        println!("    {target}")
     }
 }
 
-fn resolve_out_file(args: &Args) -> Result<PathBuf, String> {
+fn resolve_out_file(args: &PingArgs) -> Result<PathBuf, String> {
     if let Some(file) = &args.out_file {
-        if file.is_file() {
+        if file.is_file() && !args.format().appends() {
             Err(format!("File '{}' already exists.", file.display()))
         } else if file.is_dir() {
             Err(format!("'{}' is a directory.", file.display()))
@@ -236,9 +1386,9 @@ fn resolve_out_file(args: &Args) -> Result<PathBuf, String> {
     } else {
         if let Some(dir) = &args.out_dir {
             if dir.is_dir() {
-                let now = chrono::Local::now();
-                let name = dir.join(now.format("pings_%F_%H-%M-%S.json").to_string());
-                if name.exists() {
+                let format = args.format();
+                let name = dir.join(format.file_name(chrono::Local::now()));
+                if name.exists() && !format.appends() {
                     Err("Unable to generate file name. Use --out-file to explicitly specify a file path.".into())
                 } else {
                     Ok(name)
@@ -254,45 +1404,203 @@ fn resolve_out_file(args: &Args) -> Result<PathBuf, String> {
     }
 }
 
-async fn run(args: &Args) -> Report {
-    let system_time = chrono::Local::now();
-    let start_time = Instant::now();
-    let end_time = start_time + Duration::from_secs(args.duration.into());
-    let interval = Duration::from_millis(args.interval as u64);
-    let mut pinger = AsyncPinger::new();
-    pinger.set_timeout(args.timeout);
+async fn run(args: &PingArgs, out: Output<'_>) -> Result<Report, String> {
+    // only require ICMP sockets when there is something to ping
+    let icmp_pinger = if args
+        .ips_or_host_names
+        .iter()
+        .any(|t| t.probe == Probe::Icmp)
+    {
+        Some(IcmpPinger::new(args.timeout)?)
+    } else {
+        None
+    };
+
+    let schedule = Schedule::new(args)?;
+
+    let tasks = args.ips_or_host_names.iter().enumerate().map(|(idx, t)| {
+        let (icmp_pinger, schedule) = (icmp_pinger.as_ref(), &schedule);
 
-    let tasks = args.ips_or_host_names.iter().map(|t| {
-        ping_target(
-            &pinger,
-            t,
-            &interval,
-            &start_time,
-            &end_time,
-            args.display_pings,
-        )
+        async move {
+            match &t.probe {
+                Probe::Icmp => {
+                    let pinger = icmp_pinger.expect("ICMP pinger exists for ICMP targets");
+                    ping_target(pinger, idx, t, schedule, out).await
+                }
+                Probe::Tcp(port) => {
+                    let pinger = TcpPinger::new(*port, args.timeout);
+                    ping_target(&pinger, idx, t, schedule, out).await
+                }
+                Probe::Udp(port) => {
+                    let pinger = UdpPinger::new(*port, args.timeout);
+                    ping_target(&pinger, idx, t, schedule, out).await
+                }
+                Probe::Http(url) => {
+                    let pinger = HttpPinger::new(url.clone(), args.timeout);
+                    ping_target(&pinger, idx, t, schedule, out).await
+                }
+                Probe::Dns(query) => {
+                    let pinger = DnsPinger::new(query.clone(), args.timeout);
+                    ping_target(&pinger, idx, t, schedule, out).await
+                }
+            }
+        }
     });
 
     let targets = futures::future::join_all(tasks).await;
 
-    Report {
-        start_time: system_time.to_rfc3339(),
-        duration: args.duration,
-        interval: args.interval,
-        targets,
+    Ok(Report::new(args, &schedule, targets))
+}
+
+/// When and how often targets are pinged.
+struct Schedule {
+    start_time: Instant,
+    /// The wall clock time at `start_time`.
+    start_date: chrono::DateTime<chrono::Local>,
+    /// `None` to ping until interrupted.
+    end_time: Option<Instant>,
+    interval: Duration,
+    /// Closed once SIGINT or SIGTERM arrived, nothing is ever sent.
+    stop: Receiver<()>,
+}
+
+impl Schedule {
+    /// Starts the schedule now. It ends after the run time or once the process is asked to
+    /// terminate, whichever comes first.
+    fn new(args: &PingArgs) -> Result<Self, String> {
+        let (sender, stop) = channel::bounded(1);
+
+        ctrlc::set_handler(move || {
+            if sender.close() {
+                println!("Stopping, press Ctrl-C again to quit without a report.");
+            } else {
+                process::exit(130);
+            }
+        })
+        .map_err(|e| format!("Unable to handle signals: {e}"))?;
+
+        let start_date = chrono::Local::now();
+        let start_time = Instant::now();
+        let end_time = match args.duration() {
+            0 => None,
+            d => Some(start_time + Duration::from_secs(d.into())),
+        };
+
+        Ok(Self {
+            start_time,
+            start_date,
+            end_time,
+            interval: Duration::from_millis(args.interval as u64),
+            stop,
+        })
+    }
+
+    /// Whether a round of pings that would start at `now` is still part of the schedule.
+    fn is_over(&self, now: Instant) -> bool {
+        self.interrupted() || self.end_time.is_some_and(|end| now >= end)
+    }
+
+    fn interrupted(&self) -> bool {
+        self.stop.is_closed()
+    }
+
+    /// Converts the `started_at` of a ping to wall clock time.
+    fn time_of(&self, started_at: u64) -> chrono::DateTime<chrono::Local> {
+        self.start_date + chrono::Duration::microseconds(started_at as i64)
+    }
+
+    /// Waits for the next round of pings after one was started at `round_start`, returns early
+    /// when interrupted.
+    async fn wait_for_next(&self, round_start: Instant) {
+        let remaining = round_start + self.interval - Instant::now();
+
+        if !remaining.is_zero() {
+            // only returns before the timeout once the channel is closed
+            _ = timeout(remaining, self.stop.recv()).await;
+        }
     }
 }
 
-async fn ping_target(
-    pinger: &AsyncPinger,
-    target: &Target,
-    interval: &Duration,
-    start_time: &Instant,
-    end_time: &Instant,
+#[cfg(test)]
+impl Schedule {
+    /// A schedule without an end or signal handler, it stops once the returned sender is closed.
+    fn until_closed(interval: Duration) -> (Self, channel::Sender<()>) {
+        let (sender, stop) = channel::bounded(1);
+        let schedule = Self {
+            start_time: Instant::now(),
+            start_date: chrono::Local::now(),
+            end_time: None,
+            interval,
+            stop,
+        };
+        (schedule, sender)
+    }
+}
+
+/// Where the pings of a target go while they are collected, besides the [`TargetReport`].
+#[derive(Clone, Copy)]
+struct Output<'a> {
     display_pings: bool,
+    log: Option<&'a PingLog>,
+    influx: Option<&'a InfluxSink>,
+    /// Set while serving, which goes on for too long to keep any pings.
+    metrics: Option<&'a Metrics>,
+    /// Only a [`RttSketch`] of the pings is kept, see --sketch.
+    sketch: bool,
+    dashboard: Option<&'a Dashboard>,
+}
+
+impl Output<'_> {
+    /// Writes the ping to the log, InfluxDB, metrics and dashboard if there are any and returns
+    /// what should be kept in memory, if anything.
+    ///
+    /// Logged pings are already safe on disk, only their outcome is kept for the summary.
+    fn record(
+        &self,
+        schedule: &Schedule,
+        report: &TargetReport,
+        ttl: Option<u8>,
+        ping: PingResult,
+    ) -> Option<PingResult> {
+        if let Some(log) = self.log {
+            log.write(schedule, report, ttl, &ping);
+        }
+        if let Some(influx) = self.influx {
+            influx.write(schedule, report, ttl, &ping);
+        }
+        if let (Some(dashboard), None) = (self.dashboard, ttl) {
+            dashboard.observe(report, &ping);
+        }
+
+        if let Some(metrics) = self.metrics {
+            metrics.observe(report, &ping);
+        }
+
+        if self.metrics.is_some() || self.sketch {
+            None
+        } else if self.log.is_some() {
+            Some(PingResult {
+                details: Default::default(),
+                ..ping
+            })
+        } else {
+            Some(ping)
+        }
+    }
+}
+
+async fn ping_target<P: Pinger>(
+    pinger: &P,
+    idx: usize,
+    target: &Target,
+    schedule: &Schedule,
+    out: Output<'_>,
 ) -> TargetReport {
-    let mut pings: Vec<PingResult> = Vec::new();
-    let name = if display_pings {
+    let mut report = TargetReport::new(idx, target);
+    if out.sketch {
+        report.sketch = Some(RttSketch::default());
+    }
+    let name = if out.display_pings {
         Some(format!("{:>15}", target.ip))
     } else {
         None
@@ -300,36 +1608,238 @@ async fn ping_target(
 
     loop {
         let now = Instant::now();
-        if now >= *end_time {
+        if schedule.is_over(now) {
             break;
         }
 
-        let started_at = now.duration_since(*start_time).as_millis() as u32;
+        let started_at = now.duration_since(schedule.start_time).as_micros() as u64;
 
-        let ping = match pinger.send(target.ip, Buffer::new()).await.result {
-            Ok(rtt) => PingResult { started_at, rtt },
-            Err(_) => PingResult {
-                started_at,
-                rtt: u32::MAX,
-            },
-        };
+        let response = pinger.send(target.ip).await;
 
         if let Some(n) = &name {
-            println!("Reply from {n}: {:>4} ms", ping.rtt);
+            match &response.result {
+                Ok(rtt) => println!("Reply from {n}: {}", format_micros(*rtt as f64)),
+                Err(e) => println!("Error from {n}: {e}"),
+            }
+        }
+
+        let ping = PingResult {
+            started_at,
+            status: response.result.into(),
+            details: response.details,
+        };
+
+        if let Some(sketch) = &mut report.sketch {
+            sketch.record(ping.rtt());
         }
+        let ping = out.record(schedule, &report, None, ping);
+        report.pings.extend(ping);
+
+        schedule.wait_for_next(now).await;
+    }
 
-        pings.push(ping);
+    report
+}
 
-        let remaining = now + *interval - Instant::now();
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
 
-        if !remaining.is_zero() {
-            task::sleep(remaining).await;
+    use super::*;
+
+    /// A report from before the status and µs times, with one reply, a failed ping and an
+    /// HTTP target, all times in ms.
+    fn version_1_report() -> serde_json::Value {
+        json!({
+            "start_time": "2022-08-10T10:00:00+02:00",
+            "duration": 30,
+            "interval": 500,
+            "targets": [
+                {
+                    "host_name": "example.com",
+                    "ip": "93.184.216.34",
+                    "pings": [
+                        { "started_at": 0, "rtt": 12 },
+                        { "started_at": 500, "rtt": u32::MAX }
+                    ]
+                },
+                {
+                    "host_name": "example.com",
+                    "ip": "93.184.216.34",
+                    "protocol": "https",
+                    "port": 443,
+                    "url": "https://example.com:443/",
+                    "pings": [
+                        {
+                            "started_at": 1000,
+                            "rtt": 80,
+                            "dns": 5,
+                            "connect": 20,
+                            "tls": 50,
+                            "ttfb": 75,
+                            "total": 80,
+                            "http_status": 200
+                        }
+                    ]
+                }
+            ]
+        })
+    }
+
+    #[test]
+    fn version_1_reports_are_converted() {
+        let report: Report = serde_json::from_value(version_1_report()).unwrap();
+        assert_eq!(report.version, REPORT_VERSION);
+
+        let icmp = &report.targets[0];
+        assert_eq!(icmp.protocol, "icmp");
+        assert_eq!(icmp.pings[0].started_at, 0);
+        assert_eq!(icmp.pings[0].status, Status::Ok { rtt: 12_000 });
+        assert_eq!(icmp.pings[1].started_at, 500_000);
+        assert_eq!(icmp.pings[1].status, Status::Failed);
+
+        let ping = &report.targets[1].pings[0];
+        let http = ping.details.http.as_ref().unwrap();
+        assert_eq!(ping.started_at, 1_000_000);
+        assert_eq!(ping.status, Status::Ok { rtt: 80_000 });
+        assert_eq!(
+            [http.dns, http.connect, http.tls, http.ttfb, http.total],
+            [5_000, 20_000, 50_000, 75_000, 80_000].map(Some)
+        );
+        assert_eq!(http.http_status, Some(200));
+    }
+
+    #[test]
+    fn current_reports_round_trip_unchanged() {
+        let current = json!({
+            "version": REPORT_VERSION,
+            "start_time": "2024-03-01T08:00:00+00:00",
+            "duration": 0,
+            "interval": 500,
+            "interrupted": true,
+            "targets": [
+                {
+                    "host_name": "",
+                    "ip": "127.0.0.1",
+                    "protocol": "udp",
+                    "port": 7,
+                    "pings": [
+                        { "started_at": 0, "status": "ok", "rtt": 180, "seq": 0 },
+                        {
+                            "started_at": 500_000,
+                            "status": "unreachable",
+                            "icmp_type": 3,
+                            "icmp_code": 3,
+                            "seq": 1,
+                            "stray_replies": [0]
+                        },
+                        { "started_at": 1_000_000, "status": "timeout", "seq": 2 }
+                    ]
+                }
+            ]
+        });
+
+        let report: Report = serde_json::from_value(current.clone()).unwrap();
+        assert_eq!(report.targets[0].pings[1].status.name(), "unreachable");
+        assert_eq!(serde_json::to_value(&report).unwrap(), current);
+    }
+
+    #[test]
+    fn window_tables_have_a_column_per_percentile() {
+        let mut report: Report = serde_json::from_value(version_1_report()).unwrap();
+        let percentiles = [50.0, 99.0];
+        for t in &mut report.targets {
+            t.stats = Some(Stats::new(t, &percentiles, Some(1_000_000)));
         }
+
+        let tables = window_stats(&report, &percentiles);
+        assert_eq!(tables.len(), 2);
+        let cells = stats_cells(&tables[0].1, &percentiles, |w| &w.percentiles);
+        assert_eq!(
+            cells[0],
+            [
+                "From",
+                "To",
+                "Pings",
+                "Packet Loss",
+                "Min",
+                "P50",
+                "P99",
+                "Max"
+            ]
+        );
+        assert_eq!(cells[1][0], "2022-08-10 10:00:00");
+        assert_eq!(cells[1][1], "2022-08-10 10:00:01");
+        assert_eq!(cells[1][2], "2");
+        assert_eq!(cells[1][5], cells[1][4]);
+        assert_eq!(cells[1][6], cells[1][7]);
+    }
+
+    #[test]
+    fn percents_are_range_checked() {
+        assert_eq!(parse_percent("0"), Ok(0.0));
+        assert_eq!(parse_percent("99.9"), Ok(99.9));
+        assert_eq!(parse_percent(" 1.5% "), Ok(1.5));
+        assert_eq!(parse_percent("2 %"), Ok(2.0));
+        assert_eq!(parse_percent("100%"), Ok(100.0));
+        for invalid in ["-1", "100.1", "1e3", "NaN", "", "%", "five", "5%%x"] {
+            assert_eq!(
+                parse_percent(invalid),
+                Err(format!("'{invalid}' is not a percentage from 0 to 100"))
+            );
+        }
+    }
+
+    #[test]
+    fn durations_need_a_unit() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("60s"), Ok(Duration::from_secs(60)));
+        assert_eq!(parse_duration(" 5 m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("1.5h"), Ok(Duration::from_secs(5400)));
+        assert_eq!(
+            parse_duration("80"),
+            Err("'80' needs a unit: ms, s, m or h".into())
+        );
+        assert_eq!(
+            parse_duration("2d"),
+            Err("'2d' needs a unit: ms, s, m or h".into())
+        );
+        for invalid in ["0s", "-5ms", "ms", "0.0h", "1.2.3s"] {
+            assert_eq!(
+                parse_duration(invalid),
+                Err(format!("'{invalid}' is not a positive duration"))
+            );
+        }
+    }
+
+    #[test]
+    fn violations_exit_with_status_3() {
+        assert_eq!(exit_status(false, &[]), None);
+        assert_eq!(
+            exit_status(false, &["1.1.1.1: P95 is above".into()]),
+            Some(EXIT_VIOLATION)
+        );
+        assert_eq!(EXIT_VIOLATION, 3);
+        assert_eq!(exit_status(true, &[]), Some(EXIT_FAILURE));
+        assert_eq!(
+            exit_status(true, &["1.1.1.1: P95 is above".into()]),
+            Some(EXIT_FAILURE)
+        );
+    }
+
+    #[test]
+    fn ports_are_split_from_hosts() {
+        assert_eq!(split_port("example.com:443"), Some(("example.com", 443)));
+        assert_eq!(split_port("10.0.0.1:53"), Some(("10.0.0.1", 53)));
+        assert_eq!(split_port("[::1]:7"), Some(("::1", 7)));
     }
 
-    TargetReport {
-        host_name: target.host.clone().unwrap_or_default(),
-        ip: target.ip.to_string(),
-        pings,
+    #[test]
+    fn targets_without_port_are_not_split() {
+        assert_eq!(split_port("example.com"), None);
+        assert_eq!(split_port("::1"), None);
+        assert_eq!(split_port("fe80::1:80"), None);
+        assert_eq!(split_port("example.com:http"), None);
+        assert_eq!(split_port("example.com:70000"), None);
     }
 }
@@ -0,0 +1,378 @@
+use std::{
+    cell::RefCell,
+    fs::{File, OpenOptions},
+    io::{self, LineWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::{PingResult, Report, Schedule, TargetReport};
+
+mod influx;
+mod sqlite;
+
+pub use self::influx::InfluxSink;
+use self::sqlite::Database;
+
+/// File format of the report.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// A single JSON document written at the end of the run.
+    Json,
+    /// One JSON object per line and ping, appended as soon as the ping completes.
+    Ndjson,
+    /// One row per ping, appended as soon as the ping completes. Times are in µs.
+    Csv,
+    /// An SQLite database to which every run is added once it is over. Times are in µs.
+    Sqlite,
+    /// InfluxDB line protocol, one line per ping appended as soon as the ping completes. RTTs are
+    /// in µs and timestamps in ns.
+    Influx,
+}
+
+impl Format {
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "json" => Some(Format::Json),
+            "ndjson" | "jsonl" => Some(Format::Ndjson),
+            "csv" => Some(Format::Csv),
+            "sqlite" | "db" => Some(Format::Sqlite),
+            "lp" => Some(Format::Influx),
+            _ => None,
+        }
+    }
+
+    /// Name of a file for a run that starts at `now` in an `--out-dir`.
+    pub fn file_name(self, now: chrono::DateTime<chrono::Local>) -> String {
+        let extension = match self {
+            Format::Json => "json",
+            Format::Ndjson => "ndjson",
+            Format::Csv => "csv",
+            Format::Influx => "lp",
+            // a single database collects all runs
+            Format::Sqlite => return "pings.sqlite".into(),
+        };
+
+        format!("{}.{extension}", now.format("pings_%F_%H-%M-%S"))
+    }
+
+    /// Whether pings are written while the run goes on.
+    pub fn is_streamed(self) -> bool {
+        matches!(self, Format::Ndjson | Format::Csv | Format::Influx)
+    }
+
+    /// Whether runs are added to existing files.
+    pub fn appends(self) -> bool {
+        self != Format::Json
+    }
+}
+
+/// Destination of the report in the chosen [`Format`].
+pub enum ReportWriter {
+    Json(PathBuf),
+    Log(PingLog),
+    Database(Database),
+}
+
+impl ReportWriter {
+    /// Opens the file before the run, so that problems show up right away.
+    pub fn open(path: PathBuf, format: Format) -> Result<Self, String> {
+        Ok(match format {
+            Format::Json => ReportWriter::Json(path),
+            Format::Ndjson | Format::Csv | Format::Influx => {
+                ReportWriter::Log(PingLog::open(&path, format)?)
+            }
+            Format::Sqlite => ReportWriter::Database(Database::open(&path)?),
+        })
+    }
+
+    /// The log that receives the pings while the run goes on.
+    pub fn log(&self) -> Option<&PingLog> {
+        match self {
+            ReportWriter::Log(log) => Some(log),
+            _ => None,
+        }
+    }
+
+    /// Writes the report of the finished run.
+    pub async fn finish(self, report: &Report) -> Result<(), String> {
+        match self {
+            ReportWriter::Json(path) => {
+                let json = serde_json::to_string_pretty(report).unwrap();
+                async_std::fs::write(&path, json)
+                    .await
+                    .map_err(|e| format!("Unable to write '{}': {e}", path.display()))
+            }
+            // the pings are already written
+            ReportWriter::Log(_) => Ok(()),
+            ReportWriter::Database(db) => db
+                .append(report)
+                .map_err(|e| format!("Unable to add the run to the database: {e}")),
+        }
+    }
+}
+
+/// Appends every ping to a file in a streamed [`Format`], flushing each line so that it can be
+/// followed live and nothing is lost if the process dies.
+pub struct PingLog {
+    file: RefCell<Writer>,
+}
+
+enum Writer {
+    Ndjson(LineWriter<File>),
+    Csv(Box<csv::Writer<File>>),
+    Influx(LineWriter<File>),
+}
+
+#[derive(Serialize)]
+struct PingLine<'a> {
+    /// When the ping was sent.
+    time: String,
+    host_name: &'a str,
+    ip: &'a str,
+    protocol: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    port: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    query: Option<&'a str>,
+    /// The TTL of a `trace` probe.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttl: Option<u8>,
+    #[serde(flatten)]
+    ping: &'a PingResult,
+}
+
+#[derive(Serialize)]
+struct PingRow<'a> {
+    /// Start of the run the ping belongs to.
+    start_time: String,
+    host_name: &'a str,
+    ip: &'a str,
+    protocol: &'a str,
+    port: Option<u16>,
+    ttl: Option<u8>,
+    started_at_us: u64,
+    rtt_us: Option<u32>,
+    status: &'static str,
+}
+
+impl PingLog {
+    pub fn open(path: &Path, format: Format) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|f| Ok((f.metadata()?.len() == 0, f)));
+        let (empty, file) =
+            file.map_err(|e| format!("Unable to open '{}': {e}", path.display()))?;
+
+        let writer = match format {
+            // a file that is appended to already has its header
+            Format::Csv => Writer::Csv(Box::new(
+                csv::WriterBuilder::new()
+                    .has_headers(empty)
+                    .from_writer(file),
+            )),
+            Format::Influx => Writer::Influx(LineWriter::new(file)),
+            _ => Writer::Ndjson(LineWriter::new(file)),
+        };
+
+        Ok(Self {
+            file: RefCell::new(writer),
+        })
+    }
+
+    pub fn write(
+        &self,
+        schedule: &Schedule,
+        target: &TargetReport,
+        ttl: Option<u8>,
+        ping: &PingResult,
+    ) {
+        let result = match &mut *self.file.borrow_mut() {
+            Writer::Ndjson(file) => {
+                let line = PingLine {
+                    time: schedule.time_of(ping.started_at).to_rfc3339(),
+                    host_name: &target.host_name,
+                    ip: &target.ip,
+                    protocol: &target.protocol,
+                    port: target.port,
+                    url: target.url.as_deref(),
+                    query: target.query.as_deref(),
+                    ttl,
+                    ping,
+                };
+
+                serde_json::to_writer(&mut *file, &line)
+                    .map_err(io::Error::from)
+                    .and_then(|_| file.write_all(b"\n"))
+            }
+            Writer::Csv(file) => {
+                let row = PingRow {
+                    start_time: schedule.start_date.to_rfc3339(),
+                    host_name: &target.host_name,
+                    ip: &target.ip,
+                    protocol: &target.protocol,
+                    port: target.port,
+                    ttl,
+                    started_at_us: ping.started_at,
+                    rtt_us: ping.rtt(),
+                    status: ping.status.name(),
+                };
+
+                file.serialize(row)
+                    .map_err(io::Error::from)
+                    .and_then(|_| file.flush())
+            }
+            Writer::Influx(file) => {
+                let line = influx::line(schedule, target, ttl, ping);
+                writeln!(file, "{line}")
+            }
+        };
+
+        if let Err(e) = result {
+            println!("Unable to write ping: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Duration};
+
+    use async_std::task;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{pinger::Details, Probe, Status, Target};
+
+    fn target() -> TargetReport {
+        let target = Target {
+            ip: [10, 0, 0, 2].into(),
+            host: Some("echo".into()),
+            probe: Probe::Udp(7),
+        };
+        TargetReport::new(0, &target)
+    }
+
+    fn ping(started_at: u64, status: Status) -> PingResult {
+        PingResult {
+            started_at,
+            status,
+            details: Details {
+                seq: Some(started_at as u32),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Logs two pings to `path` in one run and a third one in another.
+    fn log_twice(path: &Path, format: Format) {
+        let (schedule, _stop) = Schedule::until_closed(Duration::ZERO);
+        let target = target();
+
+        let writer = ReportWriter::open(path.into(), format).unwrap();
+        let log = writer.log().unwrap();
+        log.write(&schedule, &target, None, &ping(0, Status::Ok { rtt: 180 }));
+        log.write(&schedule, &target, Some(3), &ping(1, Status::Timeout));
+        drop(writer);
+
+        let log = PingLog::open(path, format).unwrap();
+        log.write(&schedule, &target, None, &ping(2, Status::Ok { rtt: 250 }));
+    }
+
+    #[test]
+    fn ndjson_lines_are_appended() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pings.ndjson");
+        log_twice(&path, Format::Ndjson);
+
+        let lines: Vec<serde_json::Value> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+
+        let mut first = lines[0].clone();
+        assert!(first["time"].as_str().is_some());
+        first.as_object_mut().unwrap().remove("time");
+        assert_eq!(
+            first,
+            json!({
+                "host_name": "echo",
+                "ip": "10.0.0.2",
+                "protocol": "udp",
+                "port": 7,
+                "started_at": 0,
+                "status": "ok",
+                "rtt": 180,
+                "seq": 0
+            })
+        );
+        assert_eq!(lines[1]["ttl"], 3);
+        assert_eq!(lines[1]["status"], "timeout");
+        assert_eq!(lines[2]["rtt"], 250);
+    }
+
+    #[test]
+    fn csv_headers_are_only_written_to_empty_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pings.csv");
+        log_twice(&path, Format::Csv);
+
+        let text = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines[0],
+            "start_time,host_name,ip,protocol,port,ttl,started_at_us,rtt_us,status"
+        );
+        assert_eq!(lines.len(), 4);
+
+        let rows: Vec<_> = lines[1..]
+            .iter()
+            .map(|l| l.split_once(',').unwrap().1)
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                "echo,10.0.0.2,udp,7,,0,180,ok",
+                "echo,10.0.0.2,udp,7,3,1,,timeout",
+                "echo,10.0.0.2,udp,7,,2,250,ok",
+            ]
+        );
+    }
+
+    #[test]
+    fn json_reports_are_read_back_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.json");
+        let json = json!({
+            "version": crate::REPORT_VERSION,
+            "start_time": "2024-03-01T08:00:00+00:00",
+            "duration": 10,
+            "interval": 500,
+            "targets": [
+                {
+                    "host_name": "echo",
+                    "ip": "10.0.0.2",
+                    "protocol": "udp",
+                    "port": 7,
+                    "pings": [{ "started_at": 0, "status": "ok", "rtt": 180, "seq": 0 }]
+                }
+            ]
+        });
+        let report: Report = serde_json::from_value(json.clone()).unwrap();
+
+        let writer = ReportWriter::open(path.clone(), Format::Json).unwrap();
+        assert!(writer.log().is_none());
+        task::block_on(writer.finish(&report)).unwrap();
+
+        let written: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written, json);
+    }
+}
@@ -0,0 +1,311 @@
+use std::{fmt::Write, time::Duration};
+
+use async_std::{
+    channel::{self, Receiver, Sender},
+    future::timeout,
+    task::{self, JoinHandle},
+};
+
+use crate::{PingResult, Schedule, TargetReport};
+
+/// Lines that are sent in a single request at most.
+const BATCH_SIZE: usize = 5000;
+/// How long lines are collected before they are sent, even if there are fewer than a batch.
+const BATCH_DELAY: Duration = Duration::from_secs(10);
+/// Limits of a request, so that a stalled server can't hold up the end of the run.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Formats a ping as a line of the InfluxDB line protocol, e.g.
+/// `ping,host_name=localhost,ip=127.0.0.1,probe=icmp rtt=53i,status="ok" 1660000000000000000`.
+///
+/// The RTT is in µs and only present for replies, the timestamp is in ns.
+pub fn line(
+    schedule: &Schedule,
+    target: &TargetReport,
+    ttl: Option<u8>,
+    ping: &PingResult,
+) -> String {
+    // tag values can't be empty and need spaces, commas and equal signs escaped
+    let escape = |s: &str| {
+        s.replace('\\', "\\\\")
+            .replace(' ', "\\ ")
+            .replace(',', "\\,")
+            .replace('=', "\\=")
+    };
+    let mut line = String::from("ping");
+
+    if !target.host_name.is_empty() {
+        _ = write!(line, ",host_name={}", escape(&target.host_name));
+    }
+    _ = write!(line, ",ip={},probe={}", escape(&target.ip), target.protocol);
+    if let Some(port) = target.port {
+        _ = write!(line, ",port={port}");
+    }
+    if let Some(ttl) = ttl {
+        _ = write!(line, ",ttl={ttl}");
+    }
+
+    line.push(' ');
+    if let Some(rtt) = ping.rtt() {
+        _ = write!(line, "rtt={rtt}i,");
+    }
+    _ = write!(line, "status=\"{}\"", ping.status.name());
+
+    let time = schedule.time_of(ping.started_at);
+    _ = write!(line, " {}", time.timestamp_nanos_opt().unwrap_or_default());
+
+    line
+}
+
+/// POSTs lines to an InfluxDB write endpoint in batches, from a task of its own so that slow
+/// requests don't delay the pings.
+pub struct InfluxSink {
+    lines: Sender<String>,
+    task: JoinHandle<()>,
+}
+
+impl InfluxSink {
+    /// Starts sending to `url`, e.g. `http://localhost:8086/api/v2/write?bucket=pings`. The token
+    /// in `INFLUX_TOKEN` is used to authorize if it's set.
+    pub fn open(url: &str) -> Result<Self, String> {
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(CONNECT_TIMEOUT)
+            .timeout_read(READ_TIMEOUT)
+            .build();
+        let request = agent.post(url);
+        if let Err(e) = request.request_url() {
+            return Err(format!("Invalid InfluxDB URL '{url}': {e}"));
+        }
+        let request = match std::env::var("INFLUX_TOKEN") {
+            Ok(token) => request.set("Authorization", &format!("Token {token}")),
+            Err(_) => request,
+        };
+
+        let (lines, receiver) = channel::unbounded();
+        let task = task::spawn(send_batches(request, receiver));
+
+        Ok(Self { lines, task })
+    }
+
+    pub fn write(
+        &self,
+        schedule: &Schedule,
+        target: &TargetReport,
+        ttl: Option<u8>,
+        ping: &PingResult,
+    ) {
+        // the channel is only closed by `finish`
+        _ = self.lines.try_send(line(schedule, target, ttl, ping));
+    }
+
+    /// Sends the remaining lines and waits until they are written.
+    pub async fn finish(self) {
+        self.lines.close();
+        self.task.await;
+    }
+}
+
+async fn send_batches(request: ureq::Request, lines: Receiver<String>) {
+    let mut closed = false;
+
+    while !closed {
+        let mut batch = String::new();
+        let mut count = 0;
+
+        let collect = async {
+            while count < BATCH_SIZE {
+                match lines.recv().await {
+                    Ok(line) => {
+                        batch.push_str(&line);
+                        batch.push('\n');
+                        count += 1;
+                    }
+                    Err(_) => return true,
+                }
+            }
+            false
+        };
+        closed = timeout(BATCH_DELAY, collect).await.unwrap_or(false);
+
+        if count > 0 {
+            let request = request.clone();
+            let result = task::spawn_blocking(move || {
+                request
+                    .send_string(&batch)
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            })
+            .await;
+
+            if let Err(e) = result {
+                println!("Unable to write {count} ping(s) to InfluxDB: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write as _},
+        net::{TcpListener, TcpStream},
+        sync::mpsc,
+        thread,
+        time::Instant,
+    };
+
+    use chrono::{Local, TimeZone};
+
+    use super::*;
+    use crate::{Probe, Status, Target};
+
+    fn schedule() -> Schedule {
+        Schedule {
+            start_time: Instant::now(),
+            start_date: Local.timestamp_opt(1_660_000_000, 0).unwrap(),
+            end_time: None,
+            interval: Duration::from_millis(500),
+            stop: channel::bounded(1).1,
+        }
+    }
+
+    fn ping(started_at: u64, status: Status) -> PingResult {
+        PingResult {
+            started_at,
+            status,
+            details: Default::default(),
+        }
+    }
+
+    #[test]
+    fn replies_have_an_rtt_and_ns_timestamp() {
+        let target = Target {
+            ip: [127, 0, 0, 1].into(),
+            host: Some("localhost".into()),
+            probe: Probe::Icmp,
+        };
+        let report = TargetReport::new(0, &target);
+
+        assert_eq!(
+            line(
+                &schedule(),
+                &report,
+                None,
+                &ping(1_500, Status::Ok { rtt: 53 })
+            ),
+            "ping,host_name=localhost,ip=127.0.0.1,probe=icmp rtt=53i,status=\"ok\" \
+             1660000000001500000"
+        );
+    }
+
+    #[test]
+    fn lost_pings_have_no_rtt() {
+        let target = Target {
+            ip: "::1".parse().unwrap(),
+            host: None,
+            probe: Probe::Udp(7),
+        };
+        let report = TargetReport::new(0, &target);
+
+        assert_eq!(
+            line(&schedule(), &report, Some(3), &ping(0, Status::Timeout)),
+            "ping,ip=::1,probe=udp,port=7,ttl=3 status=\"timeout\" 1660000000000000000"
+        );
+    }
+
+    #[test]
+    fn tag_values_are_escaped() {
+        let target = Target {
+            ip: [10, 0, 0, 1].into(),
+            host: Some(r"my host,a=b\c".into()),
+            probe: Probe::Tcp(443),
+        };
+        let report = TargetReport::new(0, &target);
+        let line = line(&schedule(), &report, None, &ping(0, Status::Failed));
+
+        assert!(
+            line.starts_with(r"ping,host_name=my\ host\,a\=b\\c,ip=10.0.0.1,probe=tcp,port=443 "),
+            "{line}"
+        );
+    }
+
+    /// Reads an HTTP request and returns its head and body.
+    fn read_request(stream: &TcpStream) -> (String, String) {
+        let mut reader = BufReader::new(stream);
+        let mut head = String::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            head.push_str(&line);
+        }
+
+        let len = head
+            .lines()
+            .filter_map(|l| l.split_once(": "))
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+            .map_or(0, |(_, len)| len.parse().unwrap());
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body).unwrap();
+
+        (head, String::from_utf8(body).unwrap())
+    }
+
+    #[test]
+    fn batches_are_posted_and_flushed_on_finish() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/api/v2/write?bucket=pings",
+            listener.local_addr().unwrap()
+        );
+        let (requests, received) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                // passed on before answering, so that it's there once the sink is done
+                requests.send(read_request(&stream)).unwrap();
+                _ = stream.write_all(b"HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n");
+            }
+        });
+
+        let target = Target {
+            ip: [127, 0, 0, 1].into(),
+            host: None,
+            probe: Probe::Icmp,
+        };
+        let report = TargetReport::new(0, &target);
+        let schedule = schedule();
+        let pings: Vec<_> = (0..=BATCH_SIZE as u64)
+            .map(|i| ping(i, Status::Timeout))
+            .collect();
+
+        let sink = InfluxSink::open(&url).unwrap();
+        for ping in &pings {
+            sink.write(&schedule, &report, None, ping);
+        }
+
+        // a full batch is sent right away
+        let (head, body) = received.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(
+            head.starts_with("POST /api/v2/write?bucket=pings HTTP/1.1\r\n"),
+            "{head}"
+        );
+        assert_eq!(body.lines().count(), BATCH_SIZE);
+        assert_eq!(
+            body.lines().next(),
+            Some(line(&schedule, &report, None, &pings[0]).as_str())
+        );
+
+        // the rest waits for more lines until the sink is finished
+        assert!(received.try_recv().is_err());
+        task::block_on(sink.finish());
+        let (_, body) = received.try_recv().unwrap();
+        assert_eq!(
+            body,
+            line(&schedule, &report, None, &pings[BATCH_SIZE]) + "\n"
+        );
+    }
+}
@@ -0,0 +1,267 @@
+use std::path::Path;
+
+use rusqlite::{params, Connection, Transaction};
+
+use crate::{PingResult, Report, Status, TargetReport};
+
+/// Runs, their targets (including the hops of traces) and pings. Times are in µs.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    version INTEGER NOT NULL,
+    start_time TEXT NOT NULL,
+    duration INTEGER NOT NULL,
+    interval INTEGER NOT NULL,
+    interrupted INTEGER NOT NULL,
+    args TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS targets (
+    id INTEGER PRIMARY KEY,
+    run_id INTEGER NOT NULL REFERENCES runs (id),
+    -- the traced target of a hop
+    parent_id INTEGER REFERENCES targets (id),
+    ttl INTEGER,
+    host_name TEXT NOT NULL,
+    ip TEXT,
+    protocol TEXT NOT NULL,
+    port INTEGER,
+    url TEXT,
+    query TEXT
+);
+CREATE TABLE IF NOT EXISTS pings (
+    target_id INTEGER NOT NULL REFERENCES targets (id),
+    time TEXT,
+    started_at INTEGER NOT NULL,
+    status TEXT NOT NULL,
+    rtt INTEGER,
+    icmp_type INTEGER,
+    icmp_code INTEGER,
+    error TEXT,
+    -- everything else that is known about the ping as a JSON object
+    details TEXT
+);
+CREATE INDEX IF NOT EXISTS targets_run_id ON targets (run_id);
+CREATE INDEX IF NOT EXISTS pings_target_id ON pings (target_id);
+";
+
+/// An SQLite database that collects the reports of many runs.
+pub struct Database {
+    conn: Connection,
+}
+
+impl Database {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let conn = Connection::open(path)
+            .and_then(|conn| conn.execute_batch(SCHEMA).map(|_| conn))
+            .map_err(|e| format!("Unable to open database '{}': {e}", path.display()))?;
+
+        Ok(Self { conn })
+    }
+
+    /// Adds the report as a new run, all or nothing.
+    pub fn append(mut self, report: &Report) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        let args: Vec<String> = std::env::args().skip(1).collect();
+
+        tx.execute(
+            "INSERT INTO runs (version, start_time, duration, interval, interrupted, args)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                report.version,
+                report.start_time,
+                report.duration,
+                report.interval,
+                report.interrupted,
+                args.join(" "),
+            ],
+        )?;
+        let run_id = tx.last_insert_rowid();
+        let start = chrono::DateTime::parse_from_rfc3339(&report.start_time).ok();
+
+        for target in &report.targets {
+            let id = insert_target(&tx, run_id, target)?;
+            insert_pings(&tx, id, start, &target.pings)?;
+
+            for hop in &target.hops {
+                tx.execute(
+                    "INSERT INTO targets (run_id, parent_id, ttl, host_name, ip, protocol)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![run_id, id, hop.ttl, hop.host_name, hop.ip, target.protocol],
+                )?;
+                insert_pings(&tx, tx.last_insert_rowid(), start, &hop.pings)?;
+            }
+        }
+
+        tx.commit()
+    }
+}
+
+fn insert_target(tx: &Transaction, run_id: i64, target: &TargetReport) -> rusqlite::Result<i64> {
+    tx.execute(
+        "INSERT INTO targets (run_id, host_name, ip, protocol, port, url, query)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            run_id,
+            target.host_name,
+            target.ip,
+            target.protocol,
+            target.port,
+            target.url,
+            target.query,
+        ],
+    )?;
+
+    Ok(tx.last_insert_rowid())
+}
+
+fn insert_pings(
+    tx: &Transaction,
+    target_id: i64,
+    start: Option<chrono::DateTime<chrono::FixedOffset>>,
+    pings: &[PingResult],
+) -> rusqlite::Result<()> {
+    let mut insert = tx.prepare_cached(
+        "INSERT INTO pings
+            (target_id, time, started_at, status, rtt, icmp_type, icmp_code, error, details)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+    )?;
+
+    for ping in pings {
+        let time = start
+            .map(|s| (s + chrono::Duration::microseconds(ping.started_at as i64)).to_rfc3339());
+        let (icmp_type, icmp_code, error) = match &ping.status {
+            Status::Unreachable {
+                icmp_type,
+                icmp_code,
+            }
+            | Status::TtlExpired {
+                icmp_type,
+                icmp_code,
+            } => (*icmp_type, *icmp_code, None),
+            Status::SendFailed { error } | Status::Error { error } => (None, None, Some(error)),
+            _ => (None, None, None),
+        };
+        let details = serde_json::to_string(&ping.details)
+            .ok()
+            .filter(|d| d != "{}");
+
+        insert.execute(params![
+            target_id,
+            time,
+            ping.started_at,
+            ping.status.name(),
+            ping.rtt(),
+            icmp_type,
+            icmp_code,
+            error,
+            details,
+        ])?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn report() -> Report {
+        let json = json!({
+            "version": crate::REPORT_VERSION,
+            "start_time": "2024-03-01T08:00:00+00:00",
+            "duration": 10,
+            "interval": 500,
+            "targets": [
+                {
+                    "host_name": "",
+                    "ip": "10.0.0.9",
+                    "protocol": "icmp",
+                    "pings": [
+                        { "started_at": 0, "status": "ok", "rtt": 1800 },
+                        { "started_at": 500_000, "status": "timeout" }
+                    ],
+                    "hops": [
+                        {
+                            "ttl": 1,
+                            "ip": "10.0.0.1",
+                            "host_name": "gateway",
+                            "pings": [
+                                {
+                                    "started_at": 0,
+                                    "status": "ttl_expired",
+                                    "icmp_type": 11,
+                                    "icmp_code": 0
+                                }
+                            ]
+                        }
+                    ]
+                }
+            ]
+        });
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn count(conn: &Connection, table: &str) -> i64 {
+        conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |r| r.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn runs_are_appended() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pings.sqlite");
+        Database::open(&path).unwrap().append(&report()).unwrap();
+        Database::open(&path).unwrap().append(&report()).unwrap();
+
+        let conn = Connection::open(&path).unwrap();
+        assert_eq!(count(&conn, "runs"), 2);
+        assert_eq!(count(&conn, "targets"), 4);
+        assert_eq!(count(&conn, "pings"), 6);
+
+        let (time, status, rtt): (String, String, Option<u32>) = conn
+            .query_row(
+                "SELECT time, status, rtt FROM pings WHERE started_at = 500000 LIMIT 1",
+                [],
+                |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(time, "2024-03-01T08:00:00.500+00:00");
+        assert_eq!((status.as_str(), rtt), ("timeout", None));
+
+        // hops point to their traced target
+        let (ttl, icmp_type): (u8, u8) = conn
+            .query_row(
+                "SELECT t.ttl, p.icmp_type FROM targets t
+                 JOIN targets parent ON parent.id = t.parent_id
+                 JOIN pings p ON p.target_id = t.id
+                 WHERE parent.ip = '10.0.0.9' LIMIT 1",
+                [],
+                |r| Ok((r.get(0)?, r.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((ttl, icmp_type), (1, 11));
+    }
+
+    #[test]
+    fn failed_runs_add_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pings.sqlite");
+        Database::open(&path).unwrap().append(&report()).unwrap();
+
+        let db = Database::open(&path).unwrap();
+        db.conn
+            .execute_batch(
+                "CREATE TRIGGER no_timeouts BEFORE INSERT ON pings WHEN NEW.status = 'timeout'
+                 BEGIN SELECT RAISE(ABORT, 'no timeouts'); END;",
+            )
+            .unwrap();
+        assert!(db.append(&report()).is_err());
+
+        let conn = Connection::open(&path).unwrap();
+        assert_eq!(count(&conn, "runs"), 1);
+        assert_eq!(count(&conn, "targets"), 2);
+        assert_eq!(count(&conn, "pings"), 3);
+    }
+}
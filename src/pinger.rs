@@ -0,0 +1,152 @@
+use std::{fmt, io, net::IpAddr};
+
+use serde::{Deserialize, Serialize};
+
+mod dns;
+mod http;
+#[cfg(not(windows))]
+mod socket;
+mod tcp;
+mod udp;
+#[cfg(windows)]
+mod windows;
+
+pub use self::dns::{record_type_name, DnsPinger, DnsQuery};
+pub use self::http::{HttpPinger, HttpTiming, HttpUrl};
+#[cfg(not(windows))]
+pub use self::socket::SocketPinger as IcmpPinger;
+pub use self::tcp::TcpPinger;
+pub use self::udp::UdpPinger;
+#[cfg(windows)]
+pub use self::windows::WinPinger as IcmpPinger;
+
+/// Type and code of the ICMP error message that caused a [`PingError`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IcmpMessage {
+    pub kind: u8,
+    pub code: u8,
+}
+
+impl IcmpMessage {
+    /// The message a host sends for a datagram to a closed UDP port.
+    pub fn port_unreachable(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(_) => Self { kind: 3, code: 3 },
+            IpAddr::V6(_) => Self { kind: 1, code: 4 },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PingError {
+    Timeout,
+    /// The target or a router reported that the target can't be reached. The ICMP message is
+    /// `None` when the OS only reported the error kind.
+    Unreachable(Option<IcmpMessage>),
+    TtlExpired(Option<IcmpMessage>),
+    /// The probe couldn't be sent at all.
+    SendFailed(String),
+    Other(String),
+}
+
+impl PingError {
+    /// Maps an error that occurred while sending a probe.
+    pub fn send_failed(e: io::Error) -> Self {
+        match PingError::from(e) {
+            PingError::Other(e) => PingError::SendFailed(e),
+            e => e,
+        }
+    }
+
+    pub fn icmp(&self) -> Option<IcmpMessage> {
+        match self {
+            PingError::Unreachable(icmp) | PingError::TtlExpired(icmp) => *icmp,
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PingError::Timeout => write!(f, "request timed out"),
+            PingError::Unreachable(_) => write!(f, "destination unreachable"),
+            PingError::TtlExpired(_) => write!(f, "TTL expired in transit"),
+            PingError::SendFailed(e) => write!(f, "unable to send: {e}"),
+            PingError::Other(e) => write!(f, "{e}"),
+        }?;
+
+        match self.icmp() {
+            Some(icmp) => write!(f, " (ICMP type {} code {})", icmp.kind, icmp.code),
+            None => Ok(()),
+        }
+    }
+}
+
+impl From<io::Error> for PingError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::HostUnreachable | io::ErrorKind::NetworkUnreachable => {
+                PingError::Unreachable(None)
+            }
+            _ => PingError::Other(e.to_string()),
+        }
+    }
+}
+
+/// Probe specific information that is stored next to the RTT of a ping.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Details {
+    /// Sequence number of the probe.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u32>,
+    /// Sequence numbers of replies that arrived while waiting for this probe but belong to
+    /// another one, i.e. late or duplicated replies.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stray_replies: Vec<u32>,
+    #[serde(flatten)]
+    pub http: Option<HttpTiming>,
+    /// Response code of a DNS query.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rcode: Option<u8>,
+}
+
+/// The outcome of a single probe.
+pub struct Response {
+    /// The round trip time in µs.
+    pub result: Result<u32, PingError>,
+    pub details: Details,
+}
+
+impl From<Result<u32, PingError>> for Response {
+    fn from(result: Result<u32, PingError>) -> Self {
+        Self {
+            result,
+            details: Details::default(),
+        }
+    }
+}
+
+/// Answer to an echo request with a limited TTL.
+pub struct HopReply {
+    /// The router that reported the expired TTL or the target itself.
+    pub from: IpAddr,
+    /// The round trip time in µs.
+    pub rtt: u32,
+    /// Whether the echo request made it to the target.
+    pub reached: bool,
+}
+
+/// A backend that is able to send a single probe and wait for its reply.
+///
+/// Probe specific settings such as the port are part of the pinger itself.
+pub trait Pinger {
+    /// Sends one probe to `ip` and waits for its reply.
+    async fn send(&self, ip: IpAddr) -> Response;
+}
+
+/// An ICMP backend that is able to send echo requests with a limited TTL, as needed for tracing.
+pub trait HopPinger {
+    /// Sends an echo request with a limited TTL, see [`HopReply`].
+    async fn send_with_ttl(&self, ip: IpAddr, ttl: u8) -> Result<HopReply, PingError>;
+}
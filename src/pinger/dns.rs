@@ -0,0 +1,249 @@
+use std::{
+    cell::Cell,
+    net::{IpAddr, SocketAddr},
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use async_std::{future::timeout, net::UdpSocket};
+
+use super::{
+    udp::{connected_socket, recv_error},
+    Details, PingError, Pinger, Response,
+};
+
+const RECORD_TYPES: &[(&str, u16)] = &[
+    ("A", 1),
+    ("NS", 2),
+    ("CNAME", 5),
+    ("SOA", 6),
+    ("PTR", 12),
+    ("MX", 15),
+    ("TXT", 16),
+    ("AAAA", 28),
+    ("SRV", 33),
+    ("HTTPS", 65),
+    ("ANY", 255),
+];
+
+/// Longest label of a name in bytes, see RFC 1035 section 2.3.4.
+const MAX_LABEL_LEN: usize = 63;
+/// Longest name in bytes as encoded in a query, including the length bytes and the root label.
+const MAX_NAME_LEN: usize = 255;
+
+/// A DNS question sent to a resolver.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsQuery {
+    pub port: u16,
+    pub name: String,
+    pub record_type: u16,
+}
+
+impl DnsQuery {
+    /// Parses `dns://resolver[:port][/name[/type]]` into the resolver and the query. Without a name
+    /// the NS records of the root zone are queried, the type defaults to `A` otherwise.
+    pub fn parse(s: &str) -> Option<Result<(&str, Self), String>> {
+        let rest = s.strip_prefix("dns://")?;
+        let mut parts = rest.splitn(3, '/');
+        let resolver = parts.next().unwrap_or_default();
+        let (resolver, port) = crate::split_port(resolver).unwrap_or((resolver, 53));
+
+        let query = match (parts.next().filter(|n| !n.is_empty()), parts.next()) {
+            (None, _) => Self {
+                port,
+                name: ".".into(),
+                record_type: 2,
+            },
+            (Some(name), record_type) => {
+                let record_type = match record_type.map(parse_record_type) {
+                    Some(Some(t)) => t,
+                    Some(None) => return Some(Err(format!("'{s}' has an unknown record type"))),
+                    None => 1,
+                };
+                Self {
+                    port,
+                    name: name.into(),
+                    record_type,
+                }
+            }
+        };
+
+        if resolver.is_empty() {
+            return Some(Err(format!("'{s}' is missing a resolver")));
+        }
+
+        if let Err(e) = check_name(&query.name) {
+            return Some(Err(format!("'{s}' {e}")));
+        }
+
+        Some(Ok((resolver, query)))
+    }
+
+    fn to_packet(&self, id: u16) -> Vec<u8> {
+        // header: id, flags with recursion desired, one question
+        let mut packet = id.to_be_bytes().to_vec();
+        packet.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+
+        for label in self.name.split('.').filter(|l| !l.is_empty()) {
+            packet.push(label.len() as u8);
+            packet.extend_from_slice(label.as_bytes());
+        }
+
+        packet.push(0);
+        packet.extend_from_slice(&self.record_type.to_be_bytes());
+        packet.extend_from_slice(&1u16.to_be_bytes()); // class IN
+        packet
+    }
+}
+
+/// Checks that `name` fits into a query, a trailing dot is optional.
+fn check_name(name: &str) -> Result<(), String> {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.is_empty() {
+        return Ok(());
+    }
+
+    let mut len = 1;
+    for label in name.split('.') {
+        match label.len() {
+            0 => return Err("has an empty label in its name".into()),
+            l if l > MAX_LABEL_LEN => {
+                return Err(format!("has a label longer than {MAX_LABEL_LEN} bytes"))
+            }
+            l => len += l + 1,
+        }
+    }
+
+    if len > MAX_NAME_LEN {
+        return Err(format!("has a name longer than {MAX_NAME_LEN} bytes"));
+    }
+    Ok(())
+}
+
+fn parse_record_type(s: &str) -> Option<u16> {
+    RECORD_TYPES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(s))
+        .map(|(_, t)| *t)
+        .or_else(|| s.parse().ok())
+}
+
+pub fn record_type_name(record_type: u16) -> String {
+    match RECORD_TYPES.iter().find(|(_, t)| *t == record_type) {
+        Some((name, _)) => name.to_string(),
+        None => record_type.to_string(),
+    }
+}
+
+/// Sends a DNS query to a resolver and measures how long the response takes.
+///
+/// Every response counts as a reply, the response code is recorded next to the RTT.
+pub struct DnsPinger {
+    query: DnsQuery,
+    timeout: Duration,
+    socket: OnceLock<UdpSocket>,
+    id: Cell<u16>,
+}
+
+impl DnsPinger {
+    pub fn new(query: DnsQuery, timeout: u32) -> Self {
+        Self {
+            query,
+            timeout: Duration::from_millis(timeout.into()),
+            socket: OnceLock::new(),
+            id: Cell::new(std::process::id() as u16),
+        }
+    }
+
+    async fn ping(&self, ip: IpAddr, id: u16) -> Result<(u32, u8), PingError> {
+        let socket = connected_socket(&self.socket, SocketAddr::new(ip, self.query.port))
+            .map_err(PingError::send_failed)?;
+        let mut buf = [0u8; 4096];
+
+        let start = Instant::now();
+        socket
+            .send(&self.query.to_packet(id))
+            .await
+            .map_err(PingError::send_failed)?;
+
+        loop {
+            let len = socket.recv(&mut buf).await.map_err(|e| recv_error(ip, e))?;
+            let rtt = start.elapsed().as_micros() as u32;
+
+            // skip late responses to earlier queries, they have a different id
+            if len >= 12 && buf[..2] == id.to_be_bytes() {
+                return Ok((rtt, buf[3] & 0x0f));
+            }
+        }
+    }
+}
+
+impl Pinger for DnsPinger {
+    async fn send(&self, ip: IpAddr) -> Response {
+        let id = self.id.get();
+        self.id.set(id.wrapping_add(1));
+
+        let (result, rcode) = match timeout(self.timeout, self.ping(ip, id)).await {
+            Ok(Ok((rtt, rcode))) => (Ok(rtt), Some(rcode)),
+            Ok(Err(e)) => (Err(e), None),
+            Err(_) => (Err(PingError::Timeout), None),
+        };
+
+        Response {
+            result,
+            details: Details {
+                rcode,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<(&str, DnsQuery), String> {
+        DnsQuery::parse(s).unwrap()
+    }
+
+    #[test]
+    fn queries_are_parsed() {
+        let (resolver, query) = parse("dns://1.1.1.1:5353/example.com/aaaa").unwrap();
+        assert_eq!(resolver, "1.1.1.1");
+        assert_eq!(
+            query,
+            DnsQuery {
+                port: 5353,
+                name: "example.com".into(),
+                record_type: 28,
+            }
+        );
+        assert_eq!(parse("dns://1.1.1.1").unwrap().1.name, ".");
+        assert_eq!(
+            parse("dns://1.1.1.1/example.com.").unwrap().1.record_type,
+            1
+        );
+    }
+
+    #[test]
+    fn names_must_fit_into_a_query() {
+        let label = "a".repeat(MAX_LABEL_LEN);
+        assert!(parse(&format!("dns://1.1.1.1/{label}.com")).is_ok());
+        assert!(parse(&format!("dns://1.1.1.1/{label}a.com")).is_err());
+        assert!(parse("dns://1.1.1.1/example..com").is_err());
+
+        // 3 labels of 63 bytes and one of 61 take 255 bytes with their lengths and the root label
+        let longest = format!("{label}.{label}.{label}.{}", &label[2..]);
+        assert!(parse(&format!("dns://1.1.1.1/{longest}")).is_ok());
+        assert!(parse(&format!("dns://1.1.1.1/{longest}a")).is_err());
+    }
+
+    #[test]
+    fn packets_encode_the_name_as_labels() {
+        let (_, query) = parse("dns://1.1.1.1/example.com/txt").unwrap();
+        let packet = query.to_packet(0x1234);
+        assert_eq!(&packet[..2], &[0x12, 0x34]);
+        assert_eq!(&packet[12..], b"\x07example\x03com\x00\x00\x10\x00\x01");
+    }
+}
@@ -0,0 +1,348 @@
+use std::{
+    fmt, io,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_std::{
+    future::timeout,
+    net::{TcpStream, ToSocketAddrs},
+};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use futures_rustls::{
+    pki_types::ServerName,
+    rustls::{self, ClientConfig, RootCertStore},
+    TlsConnector,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{Details, PingError, Pinger, Response};
+
+/// The parts of an `http://` or `https://` URL needed to send a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpUrl {
+    pub tls: bool,
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl HttpUrl {
+    /// Parses `http(s)://host[:port][/path][?query]`, returns `None` for any other scheme.
+    pub fn parse(s: &str) -> Option<Result<Self, String>> {
+        let (tls, rest) = if let Some(rest) = s.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            (true, s.strip_prefix("https://")?)
+        };
+
+        let (authority, rest) = rest.split_at(rest.find(['/', '?', '#']).unwrap_or(rest.len()));
+        // the fragment is never sent to the server
+        let path = match rest.split('#').next().unwrap_or_default() {
+            path if path.starts_with('/') => path.to_string(),
+            query => format!("/{query}"),
+        };
+        let default_port = if tls { 443 } else { 80 };
+        let (host, port) = crate::split_port(authority).unwrap_or_else(|| {
+            let ipv6 = authority
+                .strip_prefix('[')
+                .and_then(|h| h.strip_suffix(']'));
+            (ipv6.unwrap_or(authority), default_port)
+        });
+
+        if host.is_empty() {
+            return Some(Err(format!("'{s}' is missing a host name")));
+        }
+
+        Some(Ok(Self {
+            tls,
+            host: host.into(),
+            port,
+            path,
+        }))
+    }
+
+    /// The `Host` header, see RFC 7230 section 5.4. IPv6 addresses are bracketed and the port is
+    /// left out when it is the default of the scheme.
+    fn host_header(&self) -> String {
+        let host = match self.host.parse::<IpAddr>() {
+            Ok(IpAddr::V6(ip)) => format!("[{ip}]"),
+            _ => self.host.clone(),
+        };
+        let default_port = if self.tls { 443 } else { 80 };
+
+        if self.port == default_port {
+            host
+        } else {
+            format!("{host}:{}", self.port)
+        }
+    }
+}
+
+impl fmt::Display for HttpUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = if self.tls { "https" } else { "http" };
+        match self.host.parse::<IpAddr>() {
+            Ok(IpAddr::V6(ip)) => write!(f, "{scheme}://[{ip}]:{}{}", self.port, self.path),
+            _ => write!(f, "{scheme}://{}:{}{}", self.host, self.port, self.path),
+        }
+    }
+}
+
+/// Duration of the individual phases of a request in µs, measured from the start of the probe.
+/// Phases that were skipped (DNS for IP addresses, TLS for plain HTTP) or not reached are `None`.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct HttpTiming {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttfb: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http_status: Option<u16>,
+}
+
+/// Sends a `GET` request per probe over a new connection and times each phase of it.
+///
+/// Any HTTP response counts as a reply, whatever its status code.
+pub struct HttpPinger {
+    url: HttpUrl,
+    timeout: Duration,
+    tls: TlsConnector,
+}
+
+impl HttpPinger {
+    pub fn new(url: HttpUrl, timeout: u32) -> Self {
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .expect("the ring provider supports the default protocol versions")
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        Self {
+            url,
+            timeout: Duration::from_millis(timeout.into()),
+            tls: TlsConnector::from(Arc::new(config)),
+        }
+    }
+
+    async fn ping(&self, ip: IpAddr, timing: &mut HttpTiming) -> io::Result<u32> {
+        let start = Instant::now();
+        let elapsed = || Some(start.elapsed().as_micros() as u32);
+
+        // the target was resolved once at startup, resolve again to see how long DNS takes
+        let addr = if self.url.host.parse::<IpAddr>().is_ok() {
+            SocketAddr::new(ip, self.url.port)
+        } else {
+            let addrs: Vec<_> = (self.url.host.as_str(), self.url.port)
+                .to_socket_addrs()
+                .await?
+                .collect();
+            timing.dns = elapsed();
+            addrs
+                .iter()
+                .find(|a| a.is_ipv4())
+                .or(addrs.first())
+                .copied()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address found"))?
+        };
+
+        let stream = TcpStream::connect(addr).await?;
+        timing.connect = elapsed();
+
+        let status = if self.url.tls {
+            let name = ServerName::try_from(self.url.host.clone())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            let stream = self.tls.connect(name, stream).await?;
+            timing.tls = elapsed();
+            self.request(stream, timing, elapsed).await?
+        } else {
+            self.request(stream, timing, elapsed).await?
+        };
+
+        timing.http_status = Some(status);
+        timing.total = elapsed();
+        Ok(timing.total.unwrap_or_default())
+    }
+
+    /// Sends the request and reads the whole response, returns the status code.
+    async fn request<S, F>(
+        &self,
+        mut stream: S,
+        timing: &mut HttpTiming,
+        elapsed: F,
+    ) -> io::Result<u16>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+        F: Fn() -> Option<u32>,
+    {
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: pingtest/{}\r\nAccept: */*\r\nConnection: close\r\n\r\n",
+            self.url.path,
+            self.url.host_header(),
+            env!("CARGO_PKG_VERSION")
+        );
+        stream.write_all(request.as_bytes()).await?;
+
+        let mut buf = [0u8; 8192];
+        let mut head = Vec::new();
+
+        loop {
+            let len = stream.read(&mut buf).await?;
+            if len == 0 {
+                break;
+            }
+            if timing.ttfb.is_none() {
+                timing.ttfb = elapsed();
+            }
+            // the status line is all we care about, the body is only drained
+            if head.len() < 64 {
+                head.extend_from_slice(&buf[..len]);
+            }
+        }
+
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid HTTP response");
+        let status_line = std::str::from_utf8(&head)
+            .ok()
+            .and_then(|h| h.lines().next())
+            .ok_or_else(invalid)?;
+
+        match status_line.split(' ').collect::<Vec<_>>()[..] {
+            [version, code, ..] if version.starts_with("HTTP/") => {
+                code.parse().map_err(|_| invalid())
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl Pinger for HttpPinger {
+    async fn send(&self, ip: IpAddr) -> Response {
+        let mut timing = HttpTiming::default();
+
+        let result = match timeout(self.timeout, self.ping(ip, &mut timing)).await {
+            Ok(Ok(rtt)) => Ok(rtt),
+            Ok(Err(e)) => Err(e.into()),
+            Err(_) => Err(PingError::Timeout),
+        };
+
+        Response {
+            result,
+            details: Details {
+                http: Some(timing),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::{net::TcpListener, task};
+
+    use super::*;
+
+    fn parse(s: &str) -> HttpUrl {
+        HttpUrl::parse(s).unwrap().unwrap()
+    }
+
+    fn url(tls: bool, host: &str, port: u16, path: &str) -> HttpUrl {
+        HttpUrl {
+            tls,
+            host: host.into(),
+            port,
+            path: path.into(),
+        }
+    }
+
+    #[test]
+    fn urls_are_parsed() {
+        assert_eq!(
+            parse("http://example.com"),
+            url(false, "example.com", 80, "/")
+        );
+        assert_eq!(
+            parse("https://example.com:8443/health"),
+            url(true, "example.com", 8443, "/health")
+        );
+        assert_eq!(parse("http://[::1]:8080/"), url(false, "::1", 8080, "/"));
+        assert_eq!(parse("https://[::1]/"), url(true, "::1", 443, "/"));
+        assert_eq!(parse("https://[::1]"), url(true, "::1", 443, "/"));
+    }
+
+    #[test]
+    fn queries_and_fragments_end_the_host() {
+        assert_eq!(parse("https://host?q=1"), url(true, "host", 443, "/?q=1"));
+        assert_eq!(
+            parse("http://host:81/a?q=1#top"),
+            url(false, "host", 81, "/a?q=1")
+        );
+        assert_eq!(parse("https://host#top"), url(true, "host", 443, "/"));
+    }
+
+    #[test]
+    fn host_headers_keep_brackets_and_other_ports() {
+        assert_eq!(parse("http://[::1]:8080/").host_header(), "[::1]:8080");
+        assert_eq!(parse("https://[::1]/").host_header(), "[::1]");
+        assert_eq!(
+            parse("http://example.com:8080").host_header(),
+            "example.com:8080"
+        );
+        assert_eq!(
+            parse("https://example.com:443").host_header(),
+            "example.com"
+        );
+        assert_eq!(
+            parse("https://example.com:80").host_header(),
+            "example.com:80"
+        );
+        assert_eq!(parse("http://127.0.0.1").host_header(), "127.0.0.1");
+    }
+
+    #[test]
+    fn other_urls_are_rejected() {
+        assert!(HttpUrl::parse("ftp://example.com").is_none());
+        assert!(HttpUrl::parse("example.com").is_none());
+        assert!(HttpUrl::parse("https:///path").unwrap().is_err());
+    }
+
+    #[test]
+    fn requests_to_a_local_server_are_timed() {
+        task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            task::spawn(async move {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 1024];
+                let len = stream.read(&mut buf).await.unwrap();
+                let request = String::from_utf8_lossy(&buf[..len]);
+                assert!(request.starts_with("GET /health HTTP/1.1\r\n"));
+                assert!(request.contains(&format!("\r\nHost: 127.0.0.1:{port}\r\n")));
+                stream
+                    .write_all(b"HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n")
+                    .await
+                    .unwrap();
+            });
+
+            let url = parse(&format!("http://127.0.0.1:{port}/health"));
+            let response = HttpPinger::new(url, 1000).send([127, 0, 0, 1].into()).await;
+            let timing = response.details.http.unwrap();
+
+            assert!(response.result.is_ok());
+            assert_eq!(timing.http_status, Some(204));
+            // no DNS for IP addresses and no TLS for plain HTTP
+            assert_eq!((timing.dns, timing.tls), (None, None));
+            assert!(timing.connect <= timing.ttfb && timing.ttfb <= timing.total);
+        });
+    }
+}
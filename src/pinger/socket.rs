@@ -0,0 +1,488 @@
+use std::{
+    io,
+    net::{IpAddr, SocketAddr, UdpSocket},
+    sync::atomic::{AtomicU16, Ordering},
+    time::{Duration, Instant},
+};
+
+use async_io::Async;
+use async_std::future::timeout;
+use socket2::{Domain, Protocol, Socket, Type};
+
+use super::{HopPinger, HopReply, IcmpMessage, PingError, Pinger, Response};
+
+const PAYLOAD: &[u8; 32] = b"abcdefghijklmnopqrstuvwabcdefghi";
+
+const ICMPV4_ECHO_REPLY: u8 = 0;
+const ICMPV4_UNREACHABLE: u8 = 3;
+const ICMPV4_ECHO_REQUEST: u8 = 8;
+const ICMPV4_TIME_EXCEEDED: u8 = 11;
+const ICMPV6_UNREACHABLE: u8 = 1;
+const ICMPV6_TIME_EXCEEDED: u8 = 3;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+static SEQUENCE: AtomicU16 = AtomicU16::new(0);
+
+/// ICMP pinger for Linux and other unix-like systems.
+///
+/// Prefers unprivileged ICMP datagram sockets (see `net.ipv4.ping_group_range`) and falls back to
+/// raw sockets, which require root or `CAP_NET_RAW`.
+pub struct SocketPinger {
+    timeout: Duration,
+    ident: u16,
+}
+
+impl SocketPinger {
+    pub fn new(timeout: u32) -> Result<Self, String> {
+        open_socket(Domain::IPV4, None).map_err(|e| {
+            format!(
+                "Unable to open an ICMP socket ({e}). Run as root or allow unprivileged pings \
+                 via 'sysctl net.ipv4.ping_group_range'."
+            )
+        })?;
+
+        Ok(Self {
+            timeout: Duration::from_millis(timeout.into()),
+            ident: std::process::id() as u16,
+        })
+    }
+
+    async fn ping(&self, ip: IpAddr, ttl: Option<u8>) -> io::Result<Result<HopReply, PingError>> {
+        let domain = if ip.is_ipv4() {
+            Domain::IPV4
+        } else {
+            Domain::IPV6
+        };
+        let (socket, raw) = open_socket(domain, ttl)?;
+        let socket = Async::new(socket)?;
+        let seq = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+        let request = echo_request(ip, self.ident, seq);
+        let mut buf = [0u8; 1500];
+
+        let start = Instant::now();
+        if let Err(e) = socket.send_to(&request, SocketAddr::new(ip, 0)).await {
+            return Ok(Err(PingError::send_failed(e)));
+        }
+
+        loop {
+            let (reply, from) = match socket.recv_from(&mut buf).await {
+                Ok((len, from)) => (parse_reply(ip, &buf[..len]), from.ip()),
+                // datagram sockets report ICMP errors through the error queue
+                Err(e) if !raw => match read_error_queue(socket.get_ref(), ip)? {
+                    Some((reply, from)) => (Some(reply), from),
+                    None => return Err(e),
+                },
+                Err(e) => return Err(e),
+            };
+            let rtt = start.elapsed().as_micros() as u32;
+
+            match reply {
+                Some(reply) if reply.answers(raw.then_some(self.ident), seq) => match reply {
+                    Reply::Echo { .. } if from == ip => {
+                        return Ok(Ok(HopReply {
+                            from,
+                            rtt,
+                            reached: true,
+                        }));
+                    }
+                    Reply::Echo { .. } => {}
+                    // an expired TTL is the expected answer of a router when tracing
+                    Reply::Error { error, .. } => {
+                        return Ok(match error {
+                            PingError::TtlExpired(_) if ttl.is_some() => Ok(HopReply {
+                                from,
+                                rtt,
+                                reached: false,
+                            }),
+                            e => Err(e),
+                        });
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Pinger for SocketPinger {
+    async fn send(&self, ip: IpAddr) -> Response {
+        let result = match timeout(self.timeout, self.ping(ip, None)).await {
+            Ok(Ok(result)) => result.map(|hop| hop.rtt),
+            Ok(Err(e)) => Err(e.into()),
+            Err(_) => Err(PingError::Timeout),
+        };
+
+        result.into()
+    }
+}
+
+impl HopPinger for SocketPinger {
+    async fn send_with_ttl(&self, ip: IpAddr, ttl: u8) -> Result<HopReply, PingError> {
+        match timeout(self.timeout, self.ping(ip, Some(ttl))).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(e)) => Err(e.into()),
+            Err(_) => Err(PingError::Timeout),
+        }
+    }
+}
+
+/// Opens an ICMP socket and reports whether it is a raw socket.
+fn open_socket(domain: Domain, ttl: Option<u8>) -> io::Result<(UdpSocket, bool)> {
+    let protocol = if domain == Domain::IPV4 {
+        Protocol::ICMPV4
+    } else {
+        Protocol::ICMPV6
+    };
+
+    let (socket, raw) = match Socket::new(domain, Type::DGRAM, Some(protocol)) {
+        Ok(s) => (s, false),
+        Err(_) => (Socket::new(domain, Type::RAW, Some(protocol))?, true),
+    };
+
+    match ttl {
+        Some(ttl) if domain == Domain::IPV4 => socket.set_ttl(ttl.into())?,
+        Some(ttl) => socket.set_unicast_hops_v6(ttl.into())?,
+        None => {}
+    }
+
+    if !raw {
+        enable_error_queue(&socket, domain)?;
+    }
+
+    // Async<T> wants a std type, the datagram API of UdpSocket fits ICMP sockets just fine
+    Ok((socket.into(), raw))
+}
+
+fn echo_request(ip: IpAddr, ident: u16, seq: u16) -> Vec<u8> {
+    let kind = if ip.is_ipv4() {
+        ICMPV4_ECHO_REQUEST
+    } else {
+        ICMPV6_ECHO_REQUEST
+    };
+
+    let mut packet = vec![kind, 0, 0, 0];
+    packet.extend_from_slice(&ident.to_be_bytes());
+    packet.extend_from_slice(&seq.to_be_bytes());
+    packet.extend_from_slice(PAYLOAD);
+
+    // the kernel fills in the checksum for ICMPv6 and for datagram sockets
+    if ip.is_ipv4() {
+        let checksum = checksum(&packet);
+        packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    packet
+}
+
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)]) as u32)
+        .sum::<u32>();
+
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+enum Reply {
+    Echo {
+        ident: u16,
+        seq: u16,
+    },
+    Error {
+        error: PingError,
+        ident: u16,
+        seq: u16,
+    },
+}
+
+impl Reply {
+    /// Whether this answers the request `seq`. The ident is only compared for raw sockets, which
+    /// see every ICMP packet of the host; datagram sockets get it rewritten by the kernel and only
+    /// receive their own replies.
+    fn answers(&self, ident: Option<u16>, seq: u16) -> bool {
+        let (i, s) = match self {
+            Reply::Echo { ident, seq } | Reply::Error { ident, seq, .. } => (*ident, *seq),
+        };
+        s == seq && ident.is_none_or(|ident| ident == i)
+    }
+}
+
+fn parse_reply(ip: IpAddr, packet: &[u8]) -> Option<Reply> {
+    let icmp = if ip.is_ipv4() {
+        strip_ipv4_header(packet)?
+    } else {
+        packet
+    };
+
+    if icmp.len() < 8 {
+        return None;
+    }
+
+    let ident = u16::from_be_bytes([icmp[4], icmp[5]]);
+    let seq = u16::from_be_bytes([icmp[6], icmp[7]]);
+
+    let error = match (ip, icmp[0]) {
+        (IpAddr::V4(_), ICMPV4_ECHO_REPLY) | (IpAddr::V6(_), ICMPV6_ECHO_REPLY) => {
+            return Some(Reply::Echo { ident, seq });
+        }
+        (_, kind) => icmp_error(ip, kind, icmp[1])?,
+    };
+
+    // error messages quote the IP header and the first 8 bytes of the offending request
+    let quoted = if ip.is_ipv4() {
+        strip_ipv4_header(&icmp[8..])?
+    } else {
+        icmp.get(8 + 40..)?
+    };
+
+    if quoted.len() < 8 {
+        return None;
+    }
+
+    Some(Reply::Error {
+        error,
+        ident: u16::from_be_bytes([quoted[4], quoted[5]]),
+        seq: u16::from_be_bytes([quoted[6], quoted[7]]),
+    })
+}
+
+fn icmp_error(ip: IpAddr, kind: u8, code: u8) -> Option<PingError> {
+    let icmp = Some(IcmpMessage { kind, code });
+
+    match (ip, kind) {
+        (IpAddr::V4(_), ICMPV4_UNREACHABLE) | (IpAddr::V6(_), ICMPV6_UNREACHABLE) => {
+            Some(PingError::Unreachable(icmp))
+        }
+        (IpAddr::V4(_), ICMPV4_TIME_EXCEEDED) | (IpAddr::V6(_), ICMPV6_TIME_EXCEEDED) => {
+            Some(PingError::TtlExpired(icmp))
+        }
+        _ => None,
+    }
+}
+
+/// Raw sockets (and datagram sockets on some platforms) deliver the IPv4 header as well.
+fn strip_ipv4_header(packet: &[u8]) -> Option<&[u8]> {
+    match packet.first() {
+        Some(b) if b >> 4 == 4 => packet.get(((b & 0x0f) * 4) as usize..),
+        Some(_) => Some(packet),
+        None => None,
+    }
+}
+
+/// Asks the kernel to queue ICMP errors (e.g. TTL exceeded) for datagram sockets, which otherwise
+/// never see them.
+#[cfg(target_os = "linux")]
+fn enable_error_queue(socket: &Socket, domain: Domain) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let (level, name) = if domain == Domain::IPV4 {
+        (libc::SOL_IP, libc::IP_RECVERR)
+    } else {
+        (libc::SOL_IPV6, libc::IPV6_RECVERR)
+    };
+    let enable: libc::c_int = 1;
+
+    // SAFETY: the option value is a valid c_int that outlives the call
+    let res = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &enable as *const _ as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn enable_error_queue(_socket: &Socket, _domain: Domain) -> io::Result<()> {
+    Ok(())
+}
+
+/// Reads the oldest ICMP error from the error queue of a datagram socket together with the address
+/// of the router or host that sent it.
+#[cfg(target_os = "linux")]
+fn read_error_queue(socket: &UdpSocket, ip: IpAddr) -> io::Result<Option<(Reply, IpAddr)>> {
+    use std::{
+        mem,
+        net::{Ipv4Addr, Ipv6Addr},
+        os::unix::io::AsRawFd,
+        ptr,
+    };
+
+    // the payload is the ICMP header of our request
+    let mut data = [0u8; 64];
+    let mut control = [0u8; 256];
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    // SAFETY: msghdr is a plain C struct for which all zeroes is a valid value
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = control.len() as _;
+
+    // SAFETY: all buffers referenced by msg are valid for the duration of the call
+    let len = unsafe {
+        libc::recvmsg(
+            socket.as_raw_fd(),
+            &mut msg,
+            libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT,
+        )
+    };
+
+    if len < 0 {
+        let e = io::Error::last_os_error();
+        return match e.kind() {
+            io::ErrorKind::WouldBlock => Ok(None),
+            _ => Err(e),
+        };
+    }
+
+    let request = &data[..len as usize];
+    if request.len() < 8 {
+        return Ok(None);
+    }
+
+    // SAFETY: the control buffer was filled by recvmsg, the CMSG macros stay within its bounds and
+    // the kernel places the offender address right after the sock_extended_err
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+
+        while !cmsg.is_null() {
+            let is_error = ((*cmsg).cmsg_level == libc::SOL_IP
+                && (*cmsg).cmsg_type == libc::IP_RECVERR)
+                || ((*cmsg).cmsg_level == libc::SOL_IPV6
+                    && (*cmsg).cmsg_type == libc::IPV6_RECVERR);
+
+            if is_error {
+                let err_ptr = libc::CMSG_DATA(cmsg) as *const libc::sock_extended_err;
+                let err = ptr::read_unaligned(err_ptr);
+
+                if err.ee_origin != libc::SO_EE_ORIGIN_ICMP
+                    && err.ee_origin != libc::SO_EE_ORIGIN_ICMP6
+                {
+                    return Ok(None);
+                }
+
+                let offender = err_ptr.add(1) as *const libc::sockaddr;
+                let from = match (*offender).sa_family as libc::c_int {
+                    libc::AF_INET => {
+                        let addr = ptr::read_unaligned(offender as *const libc::sockaddr_in);
+                        IpAddr::V4(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)))
+                    }
+                    libc::AF_INET6 => {
+                        let addr = ptr::read_unaligned(offender as *const libc::sockaddr_in6);
+                        IpAddr::V6(Ipv6Addr::from(addr.sin6_addr.s6_addr))
+                    }
+                    _ => return Ok(None),
+                };
+
+                let reply = icmp_error(ip, err.ee_type, err.ee_code).map(|error| Reply::Error {
+                    error,
+                    ident: u16::from_be_bytes([request[4], request[5]]),
+                    seq: u16::from_be_bytes([request[6], request[7]]),
+                });
+
+                return Ok(reply.map(|r| (r, from)));
+            }
+
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_error_queue(_socket: &UdpSocket, _ip: IpAddr) -> io::Result<Option<(Reply, IpAddr)>> {
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V4: IpAddr = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+
+    /// An IPv4 header without options as raw sockets deliver it.
+    const IPV4_HEADER: [u8; 20] = [
+        0x45, 0, 0, 60, 0, 0, 0, 0, 64, 1, 0, 0, 127, 0, 0, 1, 127, 0, 0, 1,
+    ];
+
+    #[test]
+    fn echo_requests_carry_their_checksum() {
+        let packet = echo_request(V4, 0x1234, 1);
+        assert_eq!(&packet[..8], &[8, 0, 0x3b, 0x27, 0x12, 0x34, 0, 1]);
+        assert_eq!(&packet[8..], PAYLOAD);
+        // summing a packet including its checksum gives all ones
+        assert_eq!(checksum(&packet), 0);
+
+        // odd lengths are padded with a zero byte
+        assert_eq!(checksum(&[0xff]), 0x00ff);
+        assert_eq!(checksum(&[]), 0xffff);
+    }
+
+    #[test]
+    fn echo_replies_answer_their_request_only() {
+        let mut reply = echo_request(V4, 0x1234, 7);
+        reply[0] = ICMPV4_ECHO_REPLY;
+
+        let raw = [&IPV4_HEADER[..], &reply].concat();
+        for packet in [&reply[..], &raw] {
+            let reply = parse_reply(V4, packet).unwrap();
+            assert!(matches!(
+                reply,
+                Reply::Echo {
+                    ident: 0x1234,
+                    seq: 7
+                }
+            ));
+            assert!(reply.answers(Some(0x1234), 7));
+            assert!(reply.answers(None, 7));
+            assert!(!reply.answers(Some(0x1234), 8));
+            assert!(!reply.answers(Some(0x4321), 7));
+        }
+
+        // our own request isn't a reply
+        assert!(parse_reply(V4, &echo_request(V4, 0x1234, 7)).is_none());
+        assert!(parse_reply(V4, &reply[..7]).is_none());
+    }
+
+    #[test]
+    fn time_exceeded_messages_quote_the_request() {
+        let request = echo_request(V4, 0x1234, 7);
+        let mut packet = IPV4_HEADER.to_vec();
+        packet.extend_from_slice(&[ICMPV4_TIME_EXCEEDED, 0, 0, 0, 0, 0, 0, 0]);
+        packet.extend_from_slice(&IPV4_HEADER);
+        packet.extend_from_slice(&request[..8]);
+
+        let reply = parse_reply(V4, &packet).unwrap();
+        assert!(matches!(
+            reply,
+            Reply::Error {
+                error: PingError::TtlExpired(Some(IcmpMessage { kind: 11, code: 0 })),
+                ident: 0x1234,
+                seq: 7,
+            }
+        ));
+        assert!(reply.answers(Some(0x1234), 7));
+        assert!(!reply.answers(Some(0x1234), 6));
+
+        // the quoted request must be complete
+        assert!(parse_reply(V4, &packet[..packet.len() - 1]).is_none());
+    }
+}
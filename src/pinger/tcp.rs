@@ -0,0 +1,85 @@
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use async_std::{future::timeout, net::TcpStream};
+
+use super::{PingError, Pinger, Response};
+
+/// Measures the time of the TCP three-way handshake.
+///
+/// A refused connection still means the host answered with a RST, so it is counted as a reply.
+pub struct TcpPinger {
+    port: u16,
+    timeout: Duration,
+}
+
+impl TcpPinger {
+    pub fn new(port: u16, timeout: u32) -> Self {
+        Self {
+            port,
+            timeout: Duration::from_millis(timeout.into()),
+        }
+    }
+}
+
+impl Pinger for TcpPinger {
+    async fn send(&self, ip: IpAddr) -> Response {
+        let start = Instant::now();
+        let connect = TcpStream::connect(SocketAddr::new(ip, self.port));
+
+        let result = match timeout(self.timeout, connect).await {
+            Ok(Ok(_)) => Ok(start.elapsed().as_micros() as u32),
+            Ok(Err(e)) => match e.kind() {
+                io::ErrorKind::ConnectionRefused => Ok(start.elapsed().as_micros() as u32),
+                io::ErrorKind::HostUnreachable | io::ErrorKind::NetworkUnreachable => {
+                    Err(PingError::Unreachable(None))
+                }
+                _ => Err(PingError::Other(e.to_string())),
+            },
+            Err(_) => Err(PingError::Timeout),
+        };
+
+        result.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::task;
+
+    use super::*;
+    use crate::Status;
+
+    const LOCALHOST: IpAddr = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+
+    #[test]
+    fn listening_ports_reply() {
+        task::block_on(async {
+            let listener = async_std::net::TcpListener::bind("127.0.0.1:0")
+                .await
+                .unwrap();
+            let port = listener.local_addr().unwrap().port();
+
+            let response = TcpPinger::new(port, 1000).send(LOCALHOST).await;
+            assert!(matches!(Status::from(response.result), Status::Ok { .. }));
+        });
+    }
+
+    #[test]
+    fn closed_ports_reply_with_a_reset() {
+        task::block_on(async {
+            // a port that was just free is most likely still closed
+            let port = std::net::TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap()
+                .port();
+
+            let response = TcpPinger::new(port, 1000).send(LOCALHOST).await;
+            assert!(matches!(Status::from(response.result), Status::Ok { .. }));
+        });
+    }
+}
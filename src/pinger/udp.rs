@@ -0,0 +1,157 @@
+use std::{
+    cell::Cell,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use async_std::{future::timeout, net::UdpSocket};
+
+use super::{Details, IcmpMessage, PingError, Pinger, Response};
+
+/// Prefix of every probe datagram, followed by the big endian sequence number.
+const MAGIC: &[u8; 4] = b"PTST";
+
+/// Sends sequence numbered datagrams to a UDP echo service such as `pingtest reflect`.
+///
+/// All probes of a target share one socket, so replies that arrive after their probe timed out
+/// or that were duplicated on the way are seen by the next probe and reported as stray replies.
+pub struct UdpPinger {
+    port: u16,
+    timeout: Duration,
+    socket: OnceLock<UdpSocket>,
+    seq: Cell<u32>,
+}
+
+impl UdpPinger {
+    pub fn new(port: u16, timeout: u32) -> Self {
+        Self {
+            port,
+            timeout: Duration::from_millis(timeout.into()),
+            socket: OnceLock::new(),
+            seq: Cell::new(0),
+        }
+    }
+
+    async fn ping(&self, ip: IpAddr, seq: u32, stray: &mut Vec<u32>) -> Result<u32, PingError> {
+        let socket = connected_socket(&self.socket, SocketAddr::new(ip, self.port))
+            .map_err(PingError::send_failed)?;
+        let mut packet = MAGIC.to_vec();
+        packet.extend_from_slice(&seq.to_be_bytes());
+        let mut buf = [0u8; 64];
+
+        let start = Instant::now();
+        socket.send(&packet).await.map_err(PingError::send_failed)?;
+
+        loop {
+            let len = socket.recv(&mut buf).await.map_err(|e| recv_error(ip, e))?;
+            let rtt = start.elapsed().as_micros() as u32;
+
+            if len < 8 || &buf[..4] != MAGIC {
+                continue;
+            }
+
+            match u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]) {
+                s if s == seq => return Ok(rtt),
+                s => stray.push(s),
+            }
+        }
+    }
+}
+
+/// Returns the socket in `cell`, binding and connecting it to `addr` on first use.
+pub(super) fn connected_socket(
+    cell: &OnceLock<UdpSocket>,
+    addr: SocketAddr,
+) -> io::Result<&UdpSocket> {
+    if let Some(socket) = cell.get() {
+        return Ok(socket);
+    }
+
+    let local: IpAddr = match addr {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let socket = std::net::UdpSocket::bind(SocketAddr::new(local, 0))?;
+    // a connected socket reports ICMP port unreachable messages as refused connections
+    socket.connect(addr)?;
+
+    Ok(cell.get_or_init(|| socket.into()))
+}
+
+/// Maps an error of receiving on a socket returned by [`connected_socket`].
+pub(super) fn recv_error(ip: IpAddr, e: io::Error) -> PingError {
+    match e.kind() {
+        io::ErrorKind::ConnectionRefused => {
+            PingError::Unreachable(Some(IcmpMessage::port_unreachable(ip)))
+        }
+        _ => e.into(),
+    }
+}
+
+impl Pinger for UdpPinger {
+    async fn send(&self, ip: IpAddr) -> Response {
+        let seq = self.seq.get();
+        self.seq.set(seq.wrapping_add(1));
+        let mut stray = Vec::new();
+
+        let result = match timeout(self.timeout, self.ping(ip, seq, &mut stray)).await {
+            Ok(result) => result,
+            Err(_) => Err(PingError::Timeout),
+        };
+
+        Response {
+            result,
+            details: Details {
+                seq: Some(seq),
+                stray_replies: stray,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::task;
+
+    use super::*;
+    use crate::reflect::echo;
+
+    #[test]
+    fn reflected_probes_are_answered() {
+        task::block_on(async {
+            let reflector = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let port = reflector.local_addr().unwrap().port();
+            task::spawn(async move { echo(&reflector).await });
+
+            let pinger = UdpPinger::new(port, 1000);
+            for seq in 0..3 {
+                let response = pinger.send([127, 0, 0, 1].into()).await;
+                assert!(response.result.is_ok(), "probe {seq} was not answered");
+                assert_eq!(response.details.seq, Some(seq));
+                assert!(response.details.stray_replies.is_empty());
+            }
+        });
+    }
+
+    #[test]
+    fn closed_ports_are_unreachable() {
+        task::block_on(async {
+            // a port that was just free is most likely still closed
+            let port = std::net::UdpSocket::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap()
+                .port();
+            let ip = [127, 0, 0, 1].into();
+
+            let response = UdpPinger::new(port, 1000).send(ip).await;
+            assert!(matches!(
+                response.result,
+                Err(PingError::Unreachable(Some(icmp))) if icmp == IcmpMessage::port_unreachable(ip)
+            ));
+        });
+    }
+}
@@ -0,0 +1,80 @@
+use std::{net::IpAddr, time::Instant};
+
+use winping::{AsyncPinger, Buffer, Error};
+
+use super::{HopPinger, HopReply, IcmpMessage, PingError, Pinger, Response};
+
+pub struct WinPinger {
+    pinger: AsyncPinger,
+}
+
+impl WinPinger {
+    pub fn new(timeout: u32) -> Result<Self, String> {
+        let mut pinger = AsyncPinger::new();
+        pinger.set_timeout(timeout);
+        Ok(Self { pinger })
+    }
+}
+
+impl HopPinger for WinPinger {
+    async fn send_with_ttl(&self, ip: IpAddr, ttl: u8) -> Result<HopReply, PingError> {
+        // clones share the worker thread of the original pinger
+        let mut pinger = self.pinger.clone();
+        pinger.set_ttl(ttl);
+
+        let start = Instant::now();
+        let reply = pinger.send(ip, Buffer::new()).await;
+        let rtt = start.elapsed().as_micros() as u32;
+
+        match reply.result {
+            Ok(_) => Ok(HopReply {
+                from: ip,
+                rtt,
+                reached: true,
+            }),
+            Err(Error::TtlExpired) => match reply.buffer.responding_ip() {
+                Some(from) => Ok(HopReply {
+                    from,
+                    rtt,
+                    reached: false,
+                }),
+                None => Err(map_error(ip, Error::TtlExpired)),
+            },
+            Err(e) => Err(map_error(ip, e)),
+        }
+    }
+}
+
+impl Pinger for WinPinger {
+    async fn send(&self, ip: IpAddr) -> Response {
+        // the RTT reported by Windows only has ms resolution
+        let start = Instant::now();
+        let reply = self.pinger.send(ip, Buffer::new()).await;
+        let rtt = start.elapsed().as_micros() as u32;
+
+        reply
+            .result
+            .map(|_| rtt)
+            .map_err(|e| map_error(ip, e))
+            .into()
+    }
+}
+
+/// Maps the IP status of a reply, reconstructing the ICMP message it was derived from.
+fn map_error(ip: IpAddr, e: Error) -> PingError {
+    let icmp = |v4: (u8, u8), v6: (u8, u8)| {
+        let (kind, code) = if ip.is_ipv4() { v4 } else { v6 };
+        Some(IcmpMessage { kind, code })
+    };
+
+    match e {
+        Error::Timeout => PingError::Timeout,
+        Error::NetUnreachable => PingError::Unreachable(icmp((3, 0), (1, 0))),
+        Error::HostUnreachable => PingError::Unreachable(icmp((3, 1), (1, 3))),
+        Error::ProtocolUnreachable => PingError::Unreachable(icmp((3, 2), (4, 1))),
+        Error::NeedsFragmented => PingError::Unreachable(icmp((3, 4), (2, 0))),
+        Error::TtlExpired => PingError::TtlExpired(icmp((11, 0), (3, 0))),
+        Error::ReassemblyExpired => PingError::TtlExpired(icmp((11, 1), (3, 1))),
+        e => PingError::Other(e.to_string()),
+    }
+}
@@ -0,0 +1,367 @@
+use std::{fs, ops::Range, path::PathBuf};
+
+use chrono::{Duration, NaiveDateTime, Timelike};
+use plotters::{
+    coord::{
+        ranged1d::{DefaultFormatting, KeyPointHint, Ranged},
+        types::RangedDateTime,
+    },
+    prelude::*,
+};
+use resvg::{tiny_skia, usvg};
+
+use crate::analyze::{join_datasets, load_reports, take_recent, Dataset};
+
+/// Size of the chart of a single target in px.
+const CHART_SIZE: (u32, u32) = (1600, 400);
+/// Upper end of the RTT axis in ms, slower replies are drawn at the top and so are timeouts.
+const MAX_RTT: f64 = 1000.0;
+/// RTTs in ms above which the axis is logarithmic.
+const LINEAR_RTT: f64 = 100.0;
+const RTT_TICKS: [f64; 9] = [0.0, 20.0, 40.0, 60.0, 80.0, 100.0, 200.0, 400.0, 800.0];
+/// Fonts used for the generic `sans-serif` family of PNGs, the first one installed wins.
+const SANS_SERIF_FONTS: [&str; 5] = [
+    "Arial",
+    "Helvetica",
+    "DejaVu Sans",
+    "Liberation Sans",
+    "Noto Sans",
+];
+
+#[derive(clap::Args, Debug)]
+pub struct PlotArgs {
+    /// Image to write, PNG if the file name ends with `.png`, SVG otherwise.
+    #[clap(short, long)]
+    out_file: PathBuf,
+
+    /// Length of the time window in h that ends with the latest ping of each host, 0 for all
+    /// pings.
+    #[clap(long, default_value_t = 24)]
+    recent: u32,
+
+    /// JSON reports or directories with JSON reports.
+    #[clap(required = true)]
+    reports: Vec<PathBuf>,
+}
+
+/// An RTT axis in ms that is linear up to [`LINEAR_RTT`] and logarithmic above, like matplotlib's
+/// `symlog` scale.
+struct SymlogRtt;
+
+impl SymlogRtt {
+    fn scale(ms: f64) -> f64 {
+        let ms = ms.clamp(0.0, MAX_RTT);
+        if ms <= LINEAR_RTT {
+            ms / LINEAR_RTT
+        } else {
+            1.0 + (ms / LINEAR_RTT).log10()
+        }
+    }
+}
+
+impl Ranged for SymlogRtt {
+    type FormatOption = DefaultFormatting;
+    type ValueType = f64;
+
+    fn map(&self, value: &f64, limit: (i32, i32)) -> i32 {
+        let share = Self::scale(*value) / Self::scale(MAX_RTT);
+        limit.0 + (share * (limit.1 - limit.0) as f64).round() as i32
+    }
+
+    fn key_points<Hint: KeyPointHint>(&self, _hint: Hint) -> Vec<f64> {
+        RTT_TICKS.to_vec()
+    }
+
+    fn range(&self) -> Range<f64> {
+        0.0..MAX_RTT
+    }
+}
+
+/// Labels a time with only what changes between ticks over the whole span, like matplotlib's
+/// `ConciseDateFormatter`. The date is the axis description.
+pub fn concise_label(time: &NaiveDateTime, span: Duration) -> String {
+    let format = if time.num_seconds_from_midnight() == 0 {
+        "%b %d"
+    } else if span < Duration::minutes(5) {
+        "%H:%M:%S"
+    } else {
+        "%H:%M"
+    };
+
+    time.format(format).to_string()
+}
+
+/// Draws a scatter plot of the RTTs of each host below each other, like `analysis.ipynb`.
+fn draw<DB: DrawingBackend>(
+    root: DrawingArea<DB, plotters::coord::Shift>,
+    datasets: &[Dataset],
+    times: Range<NaiveDateTime>,
+) -> Result<(), DrawingAreaErrorKind<DB::ErrorType>> {
+    let span = times.end - times.start;
+    let dates = if times.start.date() == times.end.date() {
+        times.start.format("%F").to_string()
+    } else {
+        format!("{} - {}", times.start.format("%F"), times.end.format("%F"))
+    };
+
+    root.fill(&WHITE)?;
+
+    for (i, (set, area)) in datasets
+        .iter()
+        .zip(root.split_evenly((datasets.len(), 1)))
+        .enumerate()
+    {
+        let rtts: Vec<f64> = set
+            .pings
+            .iter()
+            .filter_map(|&(_, rtt)| rtt.map(|rtt| rtt as f64 / 1000.0))
+            .collect();
+        let share = |count: usize| count as f64 / set.pings.len().max(1) as f64 * 100.0;
+        let above = |ms: f64| share(rtts.iter().filter(|&&rtt| rtt > ms).count());
+        let caption = format!(
+            "{}    >100 ms: {:.2} %, >60 ms: {:.2} %, lost: {:.2} %",
+            set.title(),
+            above(100.0),
+            above(60.0),
+            share(set.pings.len() - rtts.len())
+        );
+
+        let mut chart = ChartBuilder::on(&area)
+            .caption(caption, ("sans-serif", 20))
+            .margin(10)
+            .x_label_area_size(40)
+            .y_label_area_size(70)
+            .build_cartesian_2d(RangedDateTime::from(times.clone()), SymlogRtt)?;
+
+        chart
+            .configure_mesh()
+            .disable_x_mesh()
+            .x_labels(16)
+            .x_label_formatter(&|t| concise_label(t, span))
+            .x_desc(&dates)
+            .y_label_formatter(&|ms| format!("{ms:.0} ms"))
+            .label_style(("sans-serif", 14))
+            .axis_desc_style(("sans-serif", 14))
+            .draw()?;
+
+        for (ms, color) in [(60.0, RGBColor(255, 165, 0)), (100.0, RED)] {
+            let line = [(times.start, ms), (times.end, ms)];
+            chart.draw_series(LineSeries::new(line, color.mix(0.5)))?;
+        }
+
+        let color = Palette99::pick(i).mix(0.5);
+        let replies = set.pings.iter().filter_map(|&(time, rtt)| {
+            rtt.map(|rtt| Circle::new((time, rtt as f64 / 1000.0), 1, color.filled()))
+        });
+        chart.draw_series(replies)?;
+
+        let timeouts = set
+            .pings
+            .iter()
+            .filter(|(_, rtt)| rtt.is_none())
+            .map(|&(time, _)| Cross::new((time, MAX_RTT), 4, RED));
+        chart.draw_series(timeouts)?;
+    }
+
+    root.present()
+}
+
+/// The time from the first to the last ping of all datasets, `None` if there are no pings.
+pub fn time_range(datasets: &[Dataset]) -> Option<Range<NaiveDateTime>> {
+    let times = datasets.iter().flat_map(|set| &set.pings).map(|&(t, _)| t);
+    let (start, end) = (times.clone().min()?, times.max()?);
+
+    // a single ping still needs a time axis
+    Some(start..end.max(start + Duration::seconds(1)))
+}
+
+/// Draws the RTTs of the datasets, which must not be empty, as SVG.
+pub fn rtt_chart(datasets: &[Dataset], times: Range<NaiveDateTime>) -> Result<String, String> {
+    let size = (CHART_SIZE.0, CHART_SIZE.1 * datasets.len() as u32);
+    let mut svg = String::new();
+    {
+        let root = SVGBackend::with_string(&mut svg, size).into_drawing_area();
+        draw(root, datasets, times).map_err(|e| format!("Unable to draw the chart: {e}"))?;
+    }
+
+    Ok(svg)
+}
+
+/// Renders the SVG with the fonts installed on the system.
+fn write_png(svg: &str, path: &std::path::Path) -> Result<(), String> {
+    let mut options = usvg::Options::default();
+    let fonts = options.fontdb_mut();
+    fonts.load_system_fonts();
+
+    let installed = SANS_SERIF_FONTS.into_iter().find(|&name| {
+        fonts
+            .faces()
+            .any(|face| face.families.iter().any(|(family, _)| family == name))
+    });
+    if let Some(name) = installed {
+        fonts.set_sans_serif_family(name);
+    }
+
+    let tree = usvg::Tree::from_str(svg, &options).map_err(|e| e.to_string())?;
+    let size = tree.size().to_int_size();
+    let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height())
+        .ok_or_else(|| "The chart is too large".to_string())?;
+
+    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+    pixmap.save_png(path).map_err(|e| e.to_string())
+}
+
+pub fn plot(args: &PlotArgs) -> Result<(), String> {
+    let path = &args.out_file;
+    if path.exists() {
+        return Err(format!("File '{}' already exists.", path.display()));
+    }
+
+    let mut reports = Vec::new();
+    for path in &args.reports {
+        reports.extend(load_reports(path)?);
+    }
+
+    let mut datasets = join_datasets(&reports);
+    if args.recent > 0 {
+        take_recent(&mut datasets, Duration::hours(args.recent.into()));
+    }
+    datasets.retain(|set| !set.pings.is_empty());
+
+    let svg = match time_range(&datasets) {
+        Some(times) => rtt_chart(&datasets, times)?,
+        None => return Err("No pings found in the reports.".into()),
+    };
+
+    let result = if path.extension().is_some_and(|e| e == "png") {
+        write_png(&svg, path)
+    } else {
+        fs::write(path, svg).map_err(|e| e.to_string())
+    };
+
+    result.map_err(|e| format!("Unable to write '{}': {e}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// The value of attribute `name` of an SVG element.
+    fn attr<'a>(element: &'a str, name: &str) -> &'a str {
+        let start = element.find(&format!(" {name}=\"")).unwrap() + name.len() + 3;
+        let len = element[start..].find('"').unwrap();
+        &element[start..start + len]
+    }
+
+    /// The points of a polyline.
+    fn points(polyline: &str) -> Vec<(i32, i32)> {
+        attr(polyline, "points")
+            .split_whitespace()
+            .map(|p| p.split_once(',').unwrap())
+            .map(|(x, y)| (x.parse().unwrap(), y.parse().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn charts_mark_slow_replies_and_timeouts() {
+        let time = |s| NaiveDateTime::default() + Duration::seconds(s);
+        let sets = [Dataset {
+            host_name: "gateway".into(),
+            probe: "ICMP".into(),
+            ips: vec!["10.0.0.1".into()],
+            pings: vec![
+                (time(0), Some(20_000)),
+                (time(1), None),
+                (time(2), Some(150_000)),
+                (time(3), None),
+            ],
+            sketch: Default::default(),
+        }];
+        let svg = rtt_chart(&sets, time_range(&sets).unwrap()).unwrap();
+        let elements: Vec<&str> = svg.lines().collect();
+
+        assert!(svg.contains(
+            "gateway (10.0.0.1) ICMP    &gt;100 ms: 25.00 %, &gt;60 ms: 25.00 %, lost: 50.00 %"
+        ));
+        assert_eq!(
+            elements.iter().filter(|e| e.starts_with("<circle")).count(),
+            2
+        );
+
+        let polylines: Vec<_> = elements
+            .iter()
+            .filter(|e| e.starts_with("<polyline"))
+            .map(|e| (attr(e, "stroke"), points(e)))
+            .collect();
+
+        // the RTT axis is the first vertical line, its ticks end on it in the order of RTT_TICKS
+        let axis = polylines
+            .iter()
+            .map(|(_, points)| points)
+            .find(|p| p[0].0 == p[1].0)
+            .unwrap();
+        let (x, top) = (axis[0].0, axis[0].1.min(axis[1].1));
+        let ticks: Vec<i32> = polylines
+            .iter()
+            .filter(|(_, p)| p[1].0 == x && p[0].1 == p[1].1)
+            .map(|(_, p)| p[0].1)
+            .collect();
+        assert_eq!(ticks.len(), RTT_TICKS.len());
+
+        for (ms, color) in [(60.0, "#FFA500"), (100.0, "#FF0000")] {
+            let (_, line) = polylines.iter().find(|(c, _)| *c == color).unwrap();
+            let tick = ticks[RTT_TICKS.iter().position(|&t| t == ms).unwrap()];
+            assert_eq!((line[0].1, line[1].1), (tick, tick), "{ms} ms line");
+        }
+
+        // a cross of two lines at the top of the axis per timeout
+        let crosses: Vec<_> = elements
+            .iter()
+            .filter(|e| e.starts_with("<line") && attr(e, "stroke") == "#FF0000")
+            .collect();
+        assert_eq!(crosses.len(), 4);
+        for cross in crosses {
+            let y1: i32 = attr(cross, "y1").parse().unwrap();
+            let y2: i32 = attr(cross, "y2").parse().unwrap();
+            assert_eq!(y1 + y2, 2 * top);
+        }
+    }
+
+    #[test]
+    fn existing_files_are_not_overwritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let report = dir.path().join("report.json");
+        let json = json!({
+            "version": crate::REPORT_VERSION,
+            "start_time": "2024-03-01T08:00:00+00:00",
+            "duration": 1,
+            "interval": 500,
+            "targets": [
+                {
+                    "host_name": "",
+                    "ip": "10.0.0.1",
+                    "pings": [
+                        { "started_at": 0, "status": "ok", "rtt": 1800 },
+                        { "started_at": 500_000, "status": "timeout" }
+                    ]
+                }
+            ]
+        });
+        fs::write(&report, json.to_string()).unwrap();
+
+        let args = PlotArgs {
+            out_file: dir.path().join("chart.svg"),
+            recent: 0,
+            reports: vec![report],
+        };
+        plot(&args).unwrap();
+        let chart = fs::read_to_string(&args.out_file).unwrap();
+        assert!(chart.starts_with("<svg"));
+
+        let e = plot(&args).unwrap_err();
+        assert!(e.contains("already exists"), "{e}");
+        assert_eq!(fs::read_to_string(&args.out_file).unwrap(), chart);
+    }
+}
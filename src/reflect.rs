@@ -0,0 +1,53 @@
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+};
+
+use async_std::net::UdpSocket;
+use clap::Parser;
+
+#[derive(Parser, Debug)]
+pub struct ReflectArgs {
+    /// UDP port to listen on.
+    #[clap(short, long)]
+    port: u16,
+
+    /// Local address to listen on. Use '::' to accept IPv6 probes as well.
+    #[clap(short, long, default_value = "0.0.0.0")]
+    bind: IpAddr,
+}
+
+/// Echoes every received datagram back to its sender. This is the counterpart of `udp://` targets.
+pub async fn reflect(args: &ReflectArgs) -> Result<(), String> {
+    let addr = SocketAddr::new(args.bind, args.port);
+    let socket = UdpSocket::bind(addr)
+        .await
+        .map_err(|e| format!("Unable to listen on {addr}: {e}"))?;
+
+    println!("Reflecting UDP datagrams on {addr}");
+
+    echo(&socket).await
+}
+
+/// Echoes the datagrams received on `socket` until receiving fails.
+pub async fn echo(socket: &UdpSocket) -> Result<(), String> {
+    let mut buf = [0u8; 1500];
+
+    loop {
+        let (len, from) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            // Windows reports an unreachable sender of an earlier reply on the next receive
+            Err(e) if is_reset(&e) => continue,
+            Err(e) => return Err(e.to_string()),
+        };
+        // a single failed reply (e.g. unreachable sender) must not stop the reflector
+        _ = socket.send_to(&buf[..len], from).await;
+    }
+}
+
+fn is_reset(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionRefused
+    )
+}
@@ -0,0 +1,343 @@
+use std::{cell::RefCell, fmt::Write, net::SocketAddr, time::Duration};
+
+use async_std::{
+    future::timeout,
+    io::{ReadExt, WriteExt},
+    net::{TcpListener, TcpStream},
+};
+use futures::future::{select, Either};
+
+use crate::{run, Output, PingArgs, PingResult, Target, TargetReport};
+
+#[derive(clap::Args, Debug)]
+pub struct ServeArgs {
+    /// Address on which to serve the metrics.
+    #[clap(short, long, default_value = "0.0.0.0:9494")]
+    listen: SocketAddr,
+
+    #[clap(flatten)]
+    pub ping: PingArgs,
+}
+
+/// Upper bounds of the RTT histogram buckets in µs.
+const BUCKETS: [u32; 15] = [
+    100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000,
+    1_000_000, 2_500_000, 5_000_000,
+];
+
+/// Counts of the pings of a single target since the start.
+#[derive(Default)]
+struct TargetMetrics {
+    pings: u64,
+    lost: u64,
+    /// Replies per bucket, the last one counts those above all bounds.
+    buckets: [u64; BUCKETS.len() + 1],
+    /// Sum of all RTTs in µs.
+    rtt_sum: u64,
+    last_rtt: Option<u32>,
+}
+
+/// Metrics of all targets in the Prometheus text format, updated with every ping.
+pub struct Metrics {
+    /// The labels of each target and its metrics, in the order of the targets.
+    targets: Vec<(String, RefCell<TargetMetrics>)>,
+}
+
+impl Metrics {
+    fn new(targets: &[Target]) -> Self {
+        let targets = targets
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (labels(&TargetReport::new(i, t)), RefCell::default()))
+            .collect();
+
+        Self { targets }
+    }
+
+    pub fn observe(&self, report: &TargetReport, ping: &PingResult) {
+        let Some((_, metrics)) = self.targets.get(report.idx) else {
+            return;
+        };
+        let mut metrics = metrics.borrow_mut();
+
+        metrics.pings += 1;
+        match ping.rtt() {
+            Some(rtt) => {
+                let bucket = BUCKETS.iter().take_while(|&&b| rtt > b).count();
+                metrics.buckets[bucket] += 1;
+                metrics.rtt_sum += rtt as u64;
+                metrics.last_rtt = Some(rtt);
+            }
+            None => metrics.lost += 1,
+        }
+    }
+
+    fn render(&self) -> String {
+        let seconds = |us: u64| us as f64 / 1_000_000.0;
+        let mut out = String::new();
+
+        _ = writeln!(
+            out,
+            "# HELP pingtest_rtt_seconds Round trip time of the replies.\n\
+             # TYPE pingtest_rtt_seconds histogram"
+        );
+        for (labels, metrics) in &self.targets {
+            let metrics = metrics.borrow();
+            let mut count = 0;
+
+            for (bound, replies) in BUCKETS.iter().zip(metrics.buckets) {
+                count += replies;
+                let le = seconds(*bound as u64);
+                _ = writeln!(
+                    out,
+                    "pingtest_rtt_seconds_bucket{{{labels},le=\"{le}\"}} {count}"
+                );
+            }
+            count += metrics.buckets[BUCKETS.len()];
+            _ = writeln!(
+                out,
+                "pingtest_rtt_seconds_bucket{{{labels},le=\"+Inf\"}} {count}"
+            );
+            _ = writeln!(
+                out,
+                "pingtest_rtt_seconds_sum{{{labels}}} {}",
+                seconds(metrics.rtt_sum)
+            );
+            _ = writeln!(out, "pingtest_rtt_seconds_count{{{labels}}} {count}");
+        }
+
+        _ = writeln!(
+            out,
+            "# HELP pingtest_pings_total Pings sent.\n\
+             # TYPE pingtest_pings_total counter"
+        );
+        for (labels, metrics) in &self.targets {
+            _ = writeln!(
+                out,
+                "pingtest_pings_total{{{labels}}} {}",
+                metrics.borrow().pings
+            );
+        }
+
+        _ = writeln!(
+            out,
+            "# HELP pingtest_lost_pings_total Pings without a reply, whatever the reason.\n\
+             # TYPE pingtest_lost_pings_total counter"
+        );
+        for (labels, metrics) in &self.targets {
+            _ = writeln!(
+                out,
+                "pingtest_lost_pings_total{{{labels}}} {}",
+                metrics.borrow().lost
+            );
+        }
+
+        _ = writeln!(
+            out,
+            "# HELP pingtest_last_rtt_seconds Round trip time of the latest reply.\n\
+             # TYPE pingtest_last_rtt_seconds gauge"
+        );
+        for (labels, metrics) in &self.targets {
+            if let Some(rtt) = metrics.borrow().last_rtt {
+                _ = writeln!(
+                    out,
+                    "pingtest_last_rtt_seconds{{{labels}}} {}",
+                    seconds(rtt as u64)
+                );
+            }
+        }
+
+        out
+    }
+}
+
+/// The labels that identify a target, only the parts that are known are included.
+fn labels(report: &TargetReport) -> String {
+    let escape = |s: &str| {
+        s.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    };
+    let mut labels = format!(
+        "host_name=\"{}\",ip=\"{}\",protocol=\"{}\"",
+        escape(&report.host_name),
+        report.ip,
+        report.protocol
+    );
+
+    if let Some(port) = report.port {
+        _ = write!(labels, ",port=\"{port}\"");
+    }
+    if let Some(url) = &report.url {
+        _ = write!(labels, ",url=\"{}\"", escape(url));
+    }
+    if let Some(query) = &report.query {
+        _ = write!(labels, ",query=\"{}\"", escape(query));
+    }
+
+    labels
+}
+
+/// Pings the targets until interrupted and serves their metrics on `/metrics` meanwhile.
+///
+/// No pings are kept in memory, they only go to the metrics and the other outputs.
+pub async fn serve(args: &ServeArgs, out: Output<'_>) -> Result<(), String> {
+    let listener = TcpListener::bind(args.listen)
+        .await
+        .map_err(|e| format!("Unable to listen on {}: {e}", args.listen))?;
+    let metrics = Metrics::new(&args.ping.ips_or_host_names);
+
+    println!("Serving metrics on http://{}/metrics", args.listen);
+
+    let out = Output {
+        metrics: Some(&metrics),
+        ..out
+    };
+    let pings = Box::pin(run(&args.ping, out));
+    let requests = Box::pin(answer(&listener, &metrics));
+
+    let report = match select(pings, requests).await {
+        Either::Left((report, _)) => report,
+        Either::Right(_) => unreachable!("requests are answered until the process ends"),
+    };
+
+    report.map(|_| ())
+}
+
+/// Answers the requests to `listener` until the process ends.
+async fn answer(listener: &TcpListener, metrics: &Metrics) {
+    loop {
+        match listener.accept().await {
+            // requests are answered one after the other, a slow client only delays the next
+            Ok((stream, _)) => {
+                _ = timeout(Duration::from_secs(10), respond(stream, metrics)).await;
+            }
+            Err(e) => println!("Unable to accept connection: {e}"),
+        }
+    }
+}
+
+async fn respond(mut stream: TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    let mut buf = [0u8; 4096];
+    let mut head = Vec::new();
+
+    // only the request line matters, but the whole head is read before answering
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < 16 * 1024 {
+        let len = stream.read(&mut buf).await?;
+        if len == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..len]);
+    }
+
+    let request_line = head.split(|&b| b == b'\r').next().unwrap_or_default();
+    let (status, body) = match request_line.split(|&b| b == b' ').collect::<Vec<_>>()[..] {
+        [b"GET", b"/metrics", ..] => ("200 OK", metrics.render()),
+        [b"GET", ..] => ("404 Not Found", "Metrics are served on /metrics\n".into()),
+        _ => ("405 Method Not Allowed", String::new()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::task;
+
+    use super::*;
+    use crate::{Probe, Status};
+
+    fn ping(status: Status) -> PingResult {
+        PingResult {
+            started_at: 0,
+            status,
+            details: Default::default(),
+        }
+    }
+
+    fn targets() -> Vec<Target> {
+        vec![
+            Target {
+                ip: [10, 0, 0, 1].into(),
+                host: Some("gate\"way".into()),
+                probe: Probe::Icmp,
+            },
+            Target {
+                ip: [10, 0, 0, 2].into(),
+                host: None,
+                probe: Probe::Udp(7),
+            },
+        ]
+    }
+
+    #[test]
+    fn metrics_are_rendered_per_target() {
+        let targets = targets();
+        let metrics = Metrics::new(&targets);
+        let gateway = TargetReport::new(0, &targets[0]);
+        metrics.observe(&gateway, &ping(Status::Ok { rtt: 200 }));
+        metrics.observe(&gateway, &ping(Status::Ok { rtt: 3_000 }));
+        metrics.observe(&gateway, &ping(Status::Timeout));
+
+        let text = metrics.render();
+        let gateway = r#"host_name="gate\"way",ip="10.0.0.1",protocol="icmp""#;
+        let echo = r#"host_name="",ip="10.0.0.2",protocol="udp",port="7""#;
+        for line in [
+            format!("pingtest_rtt_seconds_bucket{{{gateway},le=\"0.0001\"}} 0"),
+            format!("pingtest_rtt_seconds_bucket{{{gateway},le=\"0.00025\"}} 1"),
+            format!("pingtest_rtt_seconds_bucket{{{gateway},le=\"0.005\"}} 2"),
+            format!("pingtest_rtt_seconds_bucket{{{gateway},le=\"+Inf\"}} 2"),
+            format!("pingtest_rtt_seconds_sum{{{gateway}}} 0.0032"),
+            format!("pingtest_rtt_seconds_count{{{gateway}}} 2"),
+            format!("pingtest_pings_total{{{gateway}}} 3"),
+            format!("pingtest_lost_pings_total{{{gateway}}} 1"),
+            format!("pingtest_last_rtt_seconds{{{gateway}}} 0.003"),
+            format!("pingtest_pings_total{{{echo}}} 0"),
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {line} in\n{text}");
+        }
+        // targets without replies have no latest RTT
+        assert!(!text.contains(&format!("pingtest_last_rtt_seconds{{{echo}}}")));
+    }
+
+    /// Sends a GET request for `path` and splits the response into its head and body.
+    async fn get(addr: SocketAddr, path: &str) -> (String, String) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {path} HTTP/1.1\r\nHost: {addr}\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.into(), body.into())
+    }
+
+    #[test]
+    fn metrics_are_served_on_their_path_only() {
+        task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let metrics = Metrics::new(&targets());
+            metrics.observe(&TargetReport::new(1, &targets()[1]), &ping(Status::Timeout));
+
+            let scrape = async {
+                let (head, body) = get(addr, "/metrics").await;
+                assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+                assert!(head.contains(&format!("\r\nContent-Length: {}\r\n", body.len())));
+                assert_eq!(body, metrics.render());
+
+                let (head, body) = get(addr, "/other").await;
+                assert!(head.starts_with("HTTP/1.1 404 Not Found\r\n"), "{head}");
+                assert!(head.contains(&format!("\r\nContent-Length: {}\r\n", body.len())));
+                assert_eq!(body, "Metrics are served on /metrics\n");
+            };
+
+            let server = answer(&listener, &metrics);
+            select(Box::pin(server), Box::pin(scrape)).await;
+        });
+    }
+}
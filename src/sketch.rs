@@ -0,0 +1,116 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::stats::interpolate;
+
+/// Each power of two of RTTs is split into `2^SUB_BUCKET_BITS` buckets of equal width, like in
+/// HdrHistogram, so a bucket is at most 1/128 of its RTTs wide.
+const SUB_BUCKET_BITS: u32 = 7;
+
+/// The distribution of the RTTs of a target in buckets whose width grows with the RTT, which
+/// needs a few KB however many pings there are.
+///
+/// Sketches with the same buckets can be merged by adding their counts, so the sketches of many
+/// runs of a target can be combined. Quantiles are accurate to half a bucket, i.e. 0.4 %.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct RttSketch {
+    /// All pings, including the lost ones.
+    pub pings: u64,
+    /// Replies per bucket by the lowest RTT in µs of the bucket.
+    pub buckets: BTreeMap<u32, u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<u32>,
+    /// Sum of the RTTs in µs, for the mean.
+    pub sum: f64,
+    /// Sum of the squared RTTs in µs², for the standard deviation.
+    pub sum_of_squares: f64,
+}
+
+/// The lowest RTT in the bucket of `rtt` and the width of the bucket.
+fn bucket(rtt: u32) -> (u32, u32) {
+    let shift = (u32::BITS - rtt.leading_zeros()).saturating_sub(SUB_BUCKET_BITS + 1);
+    ((rtt >> shift) << shift, 1 << shift)
+}
+
+impl RttSketch {
+    /// Adds a ping with its RTT in µs, `None` if it was lost.
+    pub fn record(&mut self, rtt: Option<u32>) {
+        self.pings += 1;
+
+        if let Some(rtt) = rtt {
+            *self.buckets.entry(bucket(rtt).0).or_default() += 1;
+            self.min = Some(self.min.map_or(rtt, |min| min.min(rtt)));
+            self.max = Some(self.max.map_or(rtt, |max| max.max(rtt)));
+            self.sum += rtt as f64;
+            self.sum_of_squares += (rtt as f64).powi(2);
+        }
+    }
+
+    /// Adds the pings of another sketch.
+    pub fn merge(&mut self, other: &RttSketch) {
+        self.pings += other.pings;
+        for (&bucket, &count) in &other.buckets {
+            *self.buckets.entry(bucket).or_default() += count;
+        }
+        self.min = self.min.into_iter().chain(other.min).min();
+        self.max = self.max.into_iter().chain(other.max).max();
+        self.sum += other.sum;
+        self.sum_of_squares += other.sum_of_squares;
+    }
+
+    pub fn replies(&self) -> u64 {
+        self.buckets.values().sum()
+    }
+
+    /// Replies in the buckets above the one of `rtt`, i.e. roughly those slower than `rtt`.
+    pub fn replies_above(&self, rtt: u32) -> u64 {
+        let (low, width) = bucket(rtt);
+        self.buckets
+            .range(low.saturating_add(width)..)
+            .map(|(_, c)| c)
+            .sum()
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        match self.replies() {
+            0 => None,
+            replies => Some(self.sum / replies as f64),
+        }
+    }
+
+    /// The population standard deviation, like that of the summary.
+    pub fn stddev(&self) -> Option<f64> {
+        let mean = self.mean()?;
+        let variance = self.sum_of_squares / self.replies() as f64 - mean.powi(2);
+        Some(variance.max(0.0).sqrt())
+    }
+
+    /// The `p`th percentile of the RTTs, interpolated like [`crate::stats::percentile`] between
+    /// the middles of the buckets.
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        interpolate(self.replies(), p, |idx| {
+            self.rtt_at(idx).unwrap_or_default()
+        })
+    }
+
+    /// The RTT with the `idx`th lowest reply, 0-based, as the middle of its bucket.
+    fn rtt_at(&self, idx: u64) -> Option<f64> {
+        let mut below = 0;
+
+        for (&low, &count) in &self.buckets {
+            below += count;
+            if idx < below {
+                let width = bucket(low).1;
+                let middle = low as f64 + (width - 1) as f64 / 2.0;
+                // the extremes are known exactly
+                let (min, max) = (self.min? as f64, self.max? as f64);
+                return Some(middle.clamp(min, max));
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,587 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{PingResult, TargetReport};
+
+/// Weight of a new RTT difference in the interarrival jitter, see RFC 3550 section 6.4.1.
+const JITTER_GAIN: f64 = 1.0 / 16.0;
+/// Basic R-factor of a G.711 call without any impairment.
+const BASIC_R: f64 = 93.2;
+/// Packet-loss robustness of G.711 with packet loss concealment, see ITU-T G.113 Appendix I.
+const LOSS_ROBUSTNESS: f64 = 25.1;
+
+/// Statistics of a target that are written to the report, computed once the run is over. Times
+/// are in µs.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Stats {
+    /// The RTT at each percentile given with --percentiles.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub percentiles: Vec<Percentile>,
+    /// Interarrival jitter of consecutive replies as in RFC 3550, `None` with fewer than two
+    /// replies.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jitter: Option<f64>,
+    /// Mean absolute difference of the RTTs of consecutive replies.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mean_delta: Option<f64>,
+    /// Estimated transmission rating of a call, see [`r_factor`], `None` without replies.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub r_factor: Option<f64>,
+    /// Mean opinion score from 1 (bad) to 4.5 (best) that corresponds to the R-factor.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mos: Option<f64>,
+    /// Runs of consecutive lost pings in the order they started.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outages: Vec<Outage>,
+    /// Number of outages by the number of pings they lost.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub loss_bursts: BTreeMap<usize, usize>,
+    /// Statistics of consecutive windows of time, only with --window.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub windows: Vec<Window>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Percentile {
+    /// From 0 to 100.
+    pub percentile: f64,
+    /// See [`percentile`] for how it's interpolated.
+    pub rtt: f64,
+}
+
+/// The pings of a target that were sent in a window of time. Times are in µs since the start of
+/// the run, windows without pings are left out.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Window {
+    pub start: u64,
+    pub end: u64,
+    pub pings: usize,
+    pub lost: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<u32>,
+    /// The RTT at each percentile given with --percentiles.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub percentiles: Vec<Percentile>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<u32>,
+}
+
+impl Window {
+    /// The RTT at the `p`th percentile, `None` if it wasn't computed or there were no replies.
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        rtt_at(&self.percentiles, p)
+    }
+}
+
+/// Consecutive lost pings of a target. Times are the `started_at` of pings.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Outage {
+    /// When the first lost ping was sent.
+    pub start: u64,
+    /// When the first reply after the outage was sent for, or the last lost ping if the run
+    /// ended before the target answered again.
+    pub end: u64,
+    pub duration: u64,
+    pub lost: usize,
+    /// Index of the target the outage is attributed to, see [`crate::correlate::correlate`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cause: Option<usize>,
+}
+
+impl Stats {
+    /// Computes the statistics of the pings of a target, percentiles come from its sketch if it
+    /// has one. The pings are split into windows of `window` µs if given.
+    pub fn new(target: &TargetReport, percentiles: &[f64], window: Option<u64>) -> Self {
+        let pings = &target.pings;
+        let mut rtts: Vec<u32> = pings.iter().filter_map(PingResult::rtt).collect();
+        let loss = 1.0 - rtts.len() as f64 / pings.len().max(1) as f64;
+
+        let jitter = jitter(&rtts);
+        let r_factor = mean(&rtts).map(|rtt| r_factor(rtt, jitter.unwrap_or_default(), loss));
+
+        let outages = outages(pings);
+        let mut loss_bursts = BTreeMap::new();
+        for outage in &outages {
+            *loss_bursts.entry(outage.lost).or_default() += 1;
+        }
+
+        let mean_delta = mean_delta(&rtts);
+        rtts.sort();
+        let rtt_percentiles = percentiles.iter().filter_map(|&p| {
+            let rtt = match &target.sketch {
+                Some(sketch) => sketch.percentile(p),
+                None => percentile(&rtts, p),
+            };
+            rtt.map(|rtt| Percentile { percentile: p, rtt })
+        });
+
+        Self {
+            percentiles: rtt_percentiles.collect(),
+            jitter,
+            mean_delta,
+            r_factor,
+            mos: r_factor.map(mos),
+            outages,
+            loss_bursts,
+            windows: window.map_or_else(Vec::new, |length| windows(pings, length, percentiles)),
+        }
+    }
+
+    /// The RTT at the `p`th percentile, `None` if it wasn't computed or there were no replies.
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        rtt_at(&self.percentiles, p)
+    }
+
+    /// The outage that lasted longest, the first one of those that lasted equally long.
+    pub fn longest_outage(&self) -> Option<&Outage> {
+        self.outages
+            .iter()
+            .rev()
+            .max_by_key(|outage| outage.duration)
+    }
+}
+
+fn rtt_at(percentiles: &[Percentile], p: f64) -> Option<f64> {
+    percentiles
+        .iter()
+        .find(|percentile| percentile.percentile == p)
+        .map(|percentile| percentile.rtt)
+}
+
+/// Splits the pings into consecutive windows of `length` µs from the start of the run, with the
+/// RTTs at the given percentiles.
+fn windows(pings: &[PingResult], length: u64, percentiles: &[f64]) -> Vec<Window> {
+    let length = length.max(1);
+    let mut windows: BTreeMap<u64, Vec<Option<u32>>> = BTreeMap::new();
+    for ping in pings {
+        windows
+            .entry(ping.started_at / length)
+            .or_default()
+            .push(ping.rtt());
+    }
+
+    windows
+        .into_iter()
+        .map(|(idx, pings)| {
+            let rtts = {
+                let mut rtts: Vec<u32> = pings.iter().flatten().copied().collect();
+                rtts.sort();
+                rtts
+            };
+
+            Window {
+                start: idx * length,
+                end: (idx + 1) * length,
+                pings: pings.len(),
+                lost: pings.len() - rtts.len(),
+                min: rtts.first().copied(),
+                percentiles: percentiles
+                    .iter()
+                    .filter_map(|&p| {
+                        let rtt = percentile(&rtts, p)?;
+                        Some(Percentile { percentile: p, rtt })
+                    })
+                    .collect(),
+                max: rtts.last().copied(),
+            }
+        })
+        .collect()
+}
+
+/// Groups consecutive lost pings into outages.
+fn outages(pings: &[PingResult]) -> Vec<Outage> {
+    let mut outages = Vec::new();
+    let mut current: Option<Outage> = None;
+
+    for ping in pings {
+        match (ping.rtt(), &mut current) {
+            (None, Some(outage)) => {
+                outage.end = ping.started_at;
+                outage.lost += 1;
+            }
+            (None, None) => {
+                current = Some(Outage {
+                    start: ping.started_at,
+                    end: ping.started_at,
+                    duration: 0,
+                    lost: 1,
+                    cause: None,
+                });
+            }
+            (Some(_), _) => {
+                if let Some(mut outage) = current.take() {
+                    outage.end = ping.started_at;
+                    outages.push(outage);
+                }
+            }
+        }
+    }
+    outages.extend(current);
+
+    for outage in &mut outages {
+        outage.duration = outage.end - outage.start;
+    }
+
+    outages
+}
+
+/// The `p`th percentile, from 0 to 100, of sorted values, interpolated linearly between the
+/// two closest ranks.
+///
+/// The percentile is at rank `(n - 1) * p / 100` of the `n` values counted from 0, so 0 is the
+/// minimum and 100 the maximum. This is method 7 of Hyndman and Fan (1996), the default of R and
+/// NumPy and what Excel's `PERCENTILE.INC` does.
+pub fn percentile(sorted: &[u32], p: f64) -> Option<f64> {
+    interpolate(sorted.len() as u64, p, |idx| sorted[idx as usize] as f64)
+}
+
+/// [`percentile`] of `count` values, of which `value_at` returns the one at a rank.
+pub fn interpolate(count: u64, p: f64, value_at: impl Fn(u64) -> f64) -> Option<f64> {
+    if count == 0 {
+        return None;
+    }
+
+    let rank = (count - 1) as f64 * p.clamp(0.0, 100.0) / 100.0;
+    let (below, above) = (rank.floor(), rank.ceil());
+    let (low, high) = (value_at(below as u64), value_at(above as u64));
+
+    Some(low + (high - low) * (rank - below))
+}
+
+fn mean(rtts: &[u32]) -> Option<f64> {
+    match rtts.len() {
+        0 => None,
+        len => Some(rtts.iter().map(|&rtt| rtt as f64).sum::<f64>() / len as f64),
+    }
+}
+
+/// Differences of the RTTs of consecutive replies, the transit time differences of RFC 3550 as
+/// seen by both ends together.
+fn deltas(rtts: &[u32]) -> impl Iterator<Item = f64> + '_ {
+    rtts.windows(2)
+        .map(|pair| (pair[1] as f64 - pair[0] as f64).abs())
+}
+
+/// The interarrival jitter of RFC 3550 after the last reply, a running average of [`deltas`].
+fn jitter(rtts: &[u32]) -> Option<f64> {
+    if rtts.len() < 2 {
+        return None;
+    }
+
+    Some(deltas(rtts).fold(0.0, |jitter, d| jitter + (d - jitter) * JITTER_GAIN))
+}
+
+/// The plain mean of [`deltas`], which unlike [`jitter`] weights early and late replies alike.
+fn mean_delta(rtts: &[u32]) -> Option<f64> {
+    match rtts.len() {
+        0 | 1 => None,
+        len => Some(deltas(rtts).sum::<f64>() / (len - 1) as f64),
+    }
+}
+
+/// Estimates the R-factor of a G.711 call over the path with the simplified E-model of
+/// ITU-T G.107 by Cole and Rosenbluth, from 0 (unusable) to 93.2 (perfect).
+///
+/// The mouth-to-ear delay is taken as half the mean RTT plus a jitter buffer of twice the jitter,
+/// both in µs. The loss is the share of lost pings, which are assumed to be lost at random.
+fn r_factor(mean_rtt: f64, jitter: f64, loss: f64) -> f64 {
+    let delay = (mean_rtt / 2.0 + 2.0 * jitter) / 1000.0;
+    let delay_impairment = 0.024 * delay + 0.11 * (delay - 177.3).max(0.0);
+
+    let loss = loss * 100.0;
+    let loss_impairment = 95.0 * loss / (loss + LOSS_ROBUSTNESS);
+
+    (BASIC_R - delay_impairment - loss_impairment).max(0.0)
+}
+
+/// Converts an R-factor to the mean opinion score, see ITU-T G.107 Annex B.
+fn mos(r_factor: f64) -> f64 {
+    match r_factor {
+        r if r <= 0.0 => 1.0,
+        r if r >= 100.0 => 4.5,
+        r => 1.0 + 0.035 * r + r * (r - 60.0) * (100.0 - r) * 7e-6,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sketch::RttSketch, Probe, Status, Target};
+
+    /// RTTs in µs with an outlier, the reference percentiles below are those of R's
+    /// `quantile(x, type = 7)`, NumPy's `percentile` and Python's
+    /// `statistics.quantiles(method="inclusive")`.
+    const RTTS: [u32; 12] = [
+        1240, 980, 1510, 870, 2210, 1320, 990, 1105, 30500, 1010, 1180, 1460,
+    ];
+    const REFERENCE: [(f64, f64); 9] = [
+        (0.0, 870.0),
+        (25.0, 1005.0),
+        (50.0, 1210.0),
+        (75.0, 1472.5),
+        (90.0, 2140.0),
+        (95.0, 14940.5),
+        (99.0, 27388.1),
+        (99.9, 30188.81),
+        (100.0, 30500.0),
+    ];
+
+    fn sorted(rtts: &[u32]) -> Vec<u32> {
+        let mut rtts = rtts.to_vec();
+        rtts.sort();
+        rtts
+    }
+
+    #[test]
+    fn percentiles_match_reference() {
+        let rtts = sorted(&RTTS);
+        for (p, expected) in REFERENCE {
+            let actual = percentile(&rtts, p).unwrap();
+            assert!(
+                (actual - expected).abs() < 1e-6,
+                "P{p}: {actual} != {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn percentiles_interpolate_between_ranks() {
+        // the example of linear interpolation between closest ranks on Wikipedia
+        let rtts = [15, 20, 35, 40, 50];
+        assert_eq!(percentile(&rtts, 40.0), Some(29.0));
+        assert_eq!(percentile(&rtts, 50.0), Some(35.0));
+        assert_eq!(percentile(&rtts, 75.0), Some(40.0));
+    }
+
+    #[test]
+    fn percentiles_of_few_rtts() {
+        assert_eq!(percentile(&[], 50.0), None);
+        assert_eq!(percentile(&[700], 0.0), Some(700.0));
+        assert_eq!(percentile(&[700], 99.9), Some(700.0));
+        assert_eq!(percentile(&[100, 200], 50.0), Some(150.0));
+    }
+
+    #[test]
+    fn sketch_percentiles_are_close_to_reference() {
+        let mut sketch = RttSketch::default();
+        RTTS.iter().for_each(|&rtt| sketch.record(Some(rtt)));
+        sketch.record(None);
+
+        for (p, expected) in REFERENCE {
+            let actual = sketch.percentile(p).unwrap();
+            // half a bucket of the two RTTs the percentile is interpolated between
+            assert!(
+                (actual - expected).abs() <= expected / 256.0 + 1.0,
+                "P{p}: {actual} != {expected}"
+            );
+        }
+        assert_eq!(sketch.pings, 13);
+        assert_eq!(sketch.replies(), 12);
+    }
+
+    #[test]
+    fn jitter_is_a_running_average_of_deltas() {
+        // deltas of 2, 1, 4 and 0 ms give J = 125, 179.69, 418.46 and 392.30 µs with
+        // J += (D - J) / 16
+        let rtts = [10_000, 12_000, 11_000, 15_000, 15_000];
+        assert_eq!(jitter(&rtts), Some(392.303466796875));
+        assert_eq!(mean_delta(&rtts), Some(1750.0));
+    }
+
+    #[test]
+    fn jitter_needs_two_replies() {
+        assert_eq!(jitter(&[]), None);
+        assert_eq!(jitter(&[900]), None);
+        assert_eq!(mean_delta(&[900]), None);
+        assert_eq!(jitter(&[900, 900]), Some(0.0));
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 0.005, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn r_factor_follows_the_e_model() {
+        // 20 ms mouth-to-ear delay without loss is a toll quality call
+        let r = r_factor(40_000.0, 0.0, 0.0);
+        assert_close(r, 92.72);
+        assert_close(mos(r), 4.40);
+
+        // the jitter buffer adds twice the jitter to the delay
+        assert_eq!(r_factor(20_000.0, 5_000.0, 0.0), r);
+
+        // delays above 177.3 ms weigh much more
+        assert_close(r_factor(400_000.0, 0.0, 0.0), 85.90);
+
+        let r = r_factor(40_000.0, 0.0, 0.01);
+        assert_close(r, 89.08);
+        assert_close(mos(r), 4.32);
+    }
+
+    #[test]
+    fn r_factor_and_mos_are_clamped() {
+        assert_eq!(r_factor(2_000_000.0, 0.0, 1.0), 0.0);
+        assert_eq!(mos(0.0), 1.0);
+        assert_eq!(mos(-10.0), 1.0);
+        assert_eq!(mos(100.0), 4.5);
+        assert_eq!(mos(120.0), 4.5);
+        assert_close(mos(BASIC_R), 4.41);
+    }
+
+    /// A ping every 500 ms for each character, `x` for lost ones and `.` for replies of 1 ms.
+    fn pings(pattern: &str) -> Vec<PingResult> {
+        (0..)
+            .zip(pattern.chars())
+            .map(|(i, c)| PingResult {
+                started_at: i * 500_000,
+                status: match c {
+                    'x' => Status::Timeout,
+                    _ => Status::Ok { rtt: 1000 },
+                },
+                details: Default::default(),
+            })
+            .collect()
+    }
+
+    /// `(start, end, lost)` of each outage in ms.
+    fn spans(pattern: &str) -> Vec<(u64, u64, usize)> {
+        outages(&pings(pattern))
+            .iter()
+            .map(|o| {
+                assert_eq!(o.duration, o.end - o.start);
+                (o.start / 1000, o.end / 1000, o.lost)
+            })
+            .collect()
+    }
+
+    fn loss_bursts(pattern: &str) -> Vec<(usize, usize)> {
+        let target = Target {
+            ip: [10, 0, 0, 1].into(),
+            host: None,
+            probe: Probe::Icmp,
+        };
+        let report = TargetReport {
+            pings: pings(pattern),
+            ..TargetReport::new(0, &target)
+        };
+        Stats::new(&report, &[], None)
+            .loss_bursts
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn outages_end_with_the_next_reply() {
+        assert_eq!(spans("..xxx..x."), [(1000, 2500, 3), (3500, 4000, 1)]);
+        assert_eq!(loss_bursts("..xxx..x."), [(1, 1), (3, 1)]);
+    }
+
+    #[test]
+    fn outages_at_the_start_begin_with_the_first_ping() {
+        assert_eq!(spans("xx..."), [(0, 1000, 2)]);
+    }
+
+    #[test]
+    fn outages_at_the_end_last_until_the_last_ping() {
+        assert_eq!(spans("..xxx"), [(1000, 2000, 3)]);
+        // a single lost ping at the end has no duration yet
+        assert_eq!(spans("...x"), [(1500, 1500, 1)]);
+        assert_eq!(spans("xxxx"), [(0, 1500, 4)]);
+    }
+
+    #[test]
+    fn isolated_losses_are_outages_of_their_own() {
+        assert_eq!(
+            spans(".x.x.x."),
+            [(500, 1000, 1), (1500, 2000, 1), (2500, 3000, 1)]
+        );
+        assert_eq!(loss_bursts(".x.x.x.xx."), [(1, 3), (2, 1)]);
+    }
+
+    #[test]
+    fn runs_without_losses_have_no_outages() {
+        assert!(spans("").is_empty());
+        assert!(spans("....").is_empty());
+        assert!(loss_bursts("").is_empty());
+    }
+
+    /// A ping started `ms` into the run, lost if `rtt` is `None`.
+    fn ping(ms: u64, rtt: Option<u32>) -> PingResult {
+        PingResult {
+            started_at: ms * 1000,
+            status: match rtt {
+                Some(rtt) => Status::Ok { rtt },
+                None => Status::Timeout,
+            },
+            details: Default::default(),
+        }
+    }
+
+    /// `(start, end, pings, lost)` of each window in ms.
+    fn bounds(windows: &[Window]) -> Vec<(u64, u64, usize, usize)> {
+        windows
+            .iter()
+            .map(|w| (w.start / 1000, w.end / 1000, w.pings, w.lost))
+            .collect()
+    }
+
+    #[test]
+    fn windows_start_at_their_boundary() {
+        let pings = [
+            ping(0, Some(1000)),
+            ping(999, Some(2000)),
+            ping(1000, Some(3000)),
+            ping(1999, None),
+            ping(2000, Some(4000)),
+        ];
+        let windows = windows(&pings, 1_000_000, &[]);
+        assert_eq!(
+            bounds(&windows),
+            [(0, 1000, 2, 0), (1000, 2000, 2, 1), (2000, 3000, 1, 0)]
+        );
+        assert_eq!((windows[0].min, windows[0].max), (Some(1000), Some(2000)));
+        assert_eq!((windows[1].min, windows[1].max), (Some(3000), Some(3000)));
+    }
+
+    #[test]
+    fn the_last_window_may_be_partial() {
+        // 2.5 s of pings every 500 ms in windows of a second
+        let windows = windows(&pings(".....x"), 1_000_000, &[50.0]);
+        assert_eq!(
+            bounds(&windows),
+            [(0, 1000, 2, 0), (1000, 2000, 2, 0), (2000, 3000, 2, 1)]
+        );
+        let partial = super::windows(&pings("....."), 1_000_000, &[50.0]);
+        assert_eq!(bounds(&partial)[2], (2000, 3000, 1, 0));
+        assert_eq!(partial[2].percentile(50.0), Some(1000.0));
+    }
+
+    #[test]
+    fn windows_without_pings_are_skipped() {
+        let pings = [ping(100, Some(1000)), ping(3100, Some(1000))];
+        assert_eq!(
+            bounds(&windows(&pings, 1_000_000, &[])),
+            [(0, 1000, 1, 0), (3000, 4000, 1, 0)]
+        );
+        assert!(windows(&[], 1_000_000, &[]).is_empty());
+    }
+
+    #[test]
+    fn windows_have_the_requested_percentiles() {
+        let mut pings: Vec<_> = (0..).zip(RTTS).map(|(i, rtt)| ping(i, Some(rtt))).collect();
+        pings.push(ping(1000, None));
+        let percentiles = [25.0, 50.0, 99.9];
+        let windows = windows(&pings, 1_000_000, &percentiles);
+
+        for p in percentiles {
+            let (_, expected) = REFERENCE.iter().find(|(r, _)| *r == p).unwrap();
+            let actual = windows[0].percentile(p).unwrap();
+            assert!((actual - expected).abs() < 1e-6, "P{p}: {actual}");
+        }
+        assert_eq!(windows[0].percentile(95.0), None);
+        // a window of lost pings has none
+        assert!(windows[1].percentiles.is_empty());
+        assert_eq!(windows[1].percentile(50.0), None);
+    }
+}
@@ -0,0 +1,188 @@
+use crate::{format_micros, stats, PingArgs, Report, TargetReport};
+
+/// Checks every target of the report against the thresholds given with --max-loss, --max-p95
+/// and --max-jitter and describes each threshold a target violated.
+pub fn violations(args: &PingArgs, report: &Report) -> Vec<String> {
+    report
+        .targets
+        .iter()
+        .flat_map(|t| {
+            target_violations(args, t)
+                .into_iter()
+                .map(|v| format!("{}: {v}", t.title()))
+        })
+        .collect()
+}
+
+fn target_violations(args: &PingArgs, t: &TargetReport) -> Vec<String> {
+    let mut violations = Vec::new();
+
+    let (pings, replies) = match &t.sketch {
+        Some(sketch) => (sketch.pings, sketch.replies()),
+        None => {
+            let replies = t.pings.iter().filter(|p| p.rtt().is_some()).count();
+            (t.pings.len() as u64, replies as u64)
+        }
+    };
+
+    if let Some(max) = args.max_loss {
+        let loss = match pings {
+            0 => 0.0,
+            pings => (pings - replies) as f64 / pings as f64 * 100.0,
+        };
+        if loss > max {
+            violations.push(format!(
+                "packet loss of {loss:.2} % is above --max-loss {max} %"
+            ));
+        }
+    }
+
+    if let Some(max) = args.max_p95 {
+        let p95 = match &t.sketch {
+            Some(sketch) => sketch.percentile(95.0),
+            None => {
+                let mut rtts: Vec<u32> = t.pings.iter().filter_map(|p| p.rtt()).collect();
+                rtts.sort();
+                stats::percentile(&rtts, 95.0)
+            }
+        };
+        let max = max.as_micros() as f64;
+        match p95 {
+            Some(p95) if p95 > max => violations.push(format!(
+                "P95 of {} is above --max-p95 {}",
+                format_micros(p95).trim(),
+                format_micros(max).trim()
+            )),
+            Some(_) => {}
+            None => violations.push("no replies to compare with --max-p95".into()),
+        }
+    }
+
+    if let Some(max) = args.max_jitter {
+        let jitter = match &t.stats {
+            Some(stats) => stats.jitter,
+            None => stats::Stats::new(t, &[], None).jitter,
+        };
+        let max = max.as_micros() as f64;
+        match jitter {
+            Some(jitter) if jitter > max => violations.push(format!(
+                "jitter of {} is above --max-jitter {}",
+                format_micros(jitter).trim(),
+                format_micros(max).trim()
+            )),
+            Some(_) => {}
+            None => violations.push("too few replies to compare with --max-jitter".into()),
+        }
+    }
+
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{Args, REPORT_VERSION};
+
+    fn args(thresholds: &[&str]) -> PingArgs {
+        let args = ["pingtest"].iter().chain(thresholds);
+        Args::try_parse_from(args).unwrap().ping
+    }
+
+    /// A report of one target with a ping every 500 ms, `None` for a lost one.
+    fn report(rtts: &[Option<u32>]) -> Report {
+        let pings: Vec<_> = (0..)
+            .zip(rtts)
+            .map(|(i, rtt)| match rtt {
+                Some(rtt) => json!({ "started_at": i * 500_000, "status": "ok", "rtt": rtt }),
+                None => json!({ "started_at": i * 500_000, "status": "timeout" }),
+            })
+            .collect();
+
+        serde_json::from_value(json!({
+            "version": REPORT_VERSION,
+            "start_time": "2024-03-01T08:00:00+00:00",
+            "duration": 10,
+            "interval": 500,
+            "targets": [
+                { "host_name": "", "ip": "192.0.2.1", "protocol": "icmp", "pings": pings }
+            ]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn loss_at_the_limit_is_no_violation() {
+        // 1 of 10 pings lost
+        let mut rtts = vec![Some(20_000); 9];
+        rtts.push(None);
+        let report = report(&rtts);
+
+        assert!(violations(&args(&["--max-loss", "10%"]), &report).is_empty());
+        assert_eq!(
+            violations(&args(&["--max-loss", "9.9"]), &report),
+            ["192.0.2.1 ICMP: packet loss of 10.00 % is above --max-loss 9.9 %"]
+        );
+    }
+
+    #[test]
+    fn p95_at_the_limit_is_no_violation() {
+        // RTTs of 1 to 21 ms have a P95 of 20 ms
+        let rtts: Vec<_> = (1..=21).map(|ms| Some(ms * 1000)).collect();
+        let report = report(&rtts);
+
+        assert!(violations(&args(&["--max-p95", "20ms"]), &report).is_empty());
+        assert_eq!(
+            violations(&args(&["--max-p95", "19.9ms"]), &report),
+            ["192.0.2.1 ICMP: P95 of 20.0 ms is above --max-p95 19.9 ms"]
+        );
+    }
+
+    #[test]
+    fn jitter_at_the_limit_is_no_violation() {
+        // a delta of 16 ms gives a jitter of 1 ms
+        let report = report(&[Some(10_000), Some(26_000)]);
+
+        assert!(violations(&args(&["--max-jitter", "1ms"]), &report).is_empty());
+        assert_eq!(
+            violations(&args(&["--max-jitter", "0.9ms"]), &report),
+            ["192.0.2.1 ICMP: jitter of 1.00 ms is above --max-jitter 900 µs"]
+        );
+    }
+
+    #[test]
+    fn every_violated_threshold_is_reported() {
+        let report = report(&[Some(10_000), None, Some(90_000)]);
+        let args = args(&[
+            "--max-loss",
+            "1",
+            "--max-p95",
+            "80ms",
+            "--max-jitter",
+            "2ms",
+        ]);
+
+        let violations = violations(&args, &report);
+        assert_eq!(violations.len(), 3, "{violations:?}");
+        assert!(violations[0].contains("packet loss of 33.33 %"));
+        assert!(violations[1].contains("P95"));
+        assert!(violations[2].contains("jitter"));
+    }
+
+    #[test]
+    fn thresholds_without_replies_are_violated() {
+        let report = report(&[None, None]);
+        let args = args(&["--max-p95", "1s", "--max-jitter", "1s"]);
+
+        assert_eq!(
+            violations(&args, &report),
+            [
+                "192.0.2.1 ICMP: no replies to compare with --max-p95",
+                "192.0.2.1 ICMP: too few replies to compare with --max-jitter",
+            ]
+        );
+        assert!(violations(&self::args(&[]), &report).is_empty());
+    }
+}
@@ -0,0 +1,316 @@
+use std::{net::IpAddr, time::Instant};
+
+use async_std::task;
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    format_micros,
+    pinger::{HopPinger, IcmpPinger},
+    resolve_host,
+    sketch::RttSketch,
+    Output, PingArgs, PingResult, Probe, Report, Schedule, Status, Target, TargetReport,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct TraceArgs {
+    /// Maximum number of hops (TTL) to probe.
+    #[clap(long, default_value_t = 30)]
+    max_hops: u8,
+
+    #[clap(flatten)]
+    pub ping: PingArgs,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct HopReport {
+    pub ttl: u8,
+    /// The first router that answered on this hop, `None` if none ever did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    pub host_name: String,
+    /// Further routers that answered on this hop, e.g. because of load balancing.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub other_ips: Vec<String>,
+    pub pings: Vec<PingResult>,
+    /// The RTTs of all pings, only if no pings were kept.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sketch: Option<RttSketch>,
+}
+
+#[derive(Default)]
+struct Hop {
+    ips: Vec<IpAddr>,
+    pings: Vec<PingResult>,
+    sketch: Option<RttSketch>,
+}
+
+pub async fn trace(args: &TraceArgs, out: Output<'_>) -> Result<Report, String> {
+    let ping = &args.ping;
+
+    if let Some(t) = ping
+        .ips_or_host_names
+        .iter()
+        .find(|t| t.probe != Probe::Icmp)
+    {
+        return Err(format!(
+            "Only ICMP targets can be traced, '{t}' is not one."
+        ));
+    }
+
+    let pinger = IcmpPinger::new(ping.timeout)?;
+    let schedule = Schedule::new(ping)?;
+
+    let tasks = ping
+        .ips_or_host_names
+        .iter()
+        .enumerate()
+        .map(|(idx, t)| trace_target(&pinger, idx, t, args.max_hops, &schedule, out));
+
+    let targets = join_all(tasks).await;
+
+    Ok(Report::new(ping, &schedule, targets))
+}
+
+/// Probes all hops to the target once per interval, like mtr.
+async fn trace_target<P: HopPinger>(
+    pinger: &P,
+    idx: usize,
+    target: &Target,
+    max_hops: u8,
+    schedule: &Schedule,
+    out: Output<'_>,
+) -> TargetReport {
+    let report = TargetReport::new(idx, target);
+    let mut hops: Vec<Hop> = (0..max_hops)
+        .map(|_| Hop {
+            sketch: out.sketch.then(RttSketch::default),
+            ..Default::default()
+        })
+        .collect();
+    // shrinks to the distance of the target once it answered
+    let mut hop_count = max_hops;
+    let mut reached = false;
+    // when each round started, only counted if no pings are kept
+    let mut rounds = Vec::new();
+    let mut round_count = 0;
+
+    loop {
+        let now = Instant::now();
+        if schedule.is_over(now) {
+            break;
+        }
+
+        let started_at = now.duration_since(schedule.start_time).as_micros() as u64;
+        round_count += 1;
+        if !out.sketch {
+            rounds.push(started_at);
+        }
+        let probes = (1..=hop_count).map(|ttl| pinger.send_with_ttl(target.ip, ttl));
+
+        for (ttl, reply) in (1..).zip(join_all(probes).await) {
+            let hop = &mut hops[ttl as usize - 1];
+
+            let status = match reply {
+                Ok(reply) => {
+                    if out.display_pings {
+                        let rtt = format_micros(reply.rtt as f64);
+                        println!("Hop {ttl:>2} from {:>15}: {rtt}", reply.from);
+                    }
+                    if !hop.ips.contains(&reply.from) {
+                        hop.ips.push(reply.from);
+                    }
+                    if reply.reached && ttl <= hop_count {
+                        hop_count = ttl;
+                        reached = true;
+                    }
+                    Status::Ok { rtt: reply.rtt }
+                }
+                Err(e) => Status::from(Err(e)),
+            };
+
+            let ping = PingResult {
+                started_at,
+                status,
+                details: Default::default(),
+            };
+            if let Some(sketch) = &mut hop.sketch {
+                sketch.record(ping.rtt());
+            }
+            hop.pings
+                .extend(out.record(schedule, &report, Some(ttl), ping));
+        }
+
+        schedule.wait_for_next(now).await;
+    }
+
+    // drop everything behind the target and trailing hops that never answered
+    hops.truncate(hop_count as usize);
+    while hops.last().is_some_and(|h| h.ips.is_empty()) {
+        hops.pop();
+    }
+
+    // the target itself counts as lost in every round it didn't answer
+    let (pings, sketch) = match hops.last() {
+        Some(hop) if reached => (hop.pings.clone(), hop.sketch.clone()),
+        _ => {
+            let pings = rounds.into_iter().map(|started_at| PingResult {
+                started_at,
+                status: Status::Timeout,
+                details: Default::default(),
+            });
+            let sketch = out.sketch.then(|| RttSketch {
+                pings: round_count,
+                ..Default::default()
+            });
+            (pings.collect(), sketch)
+        }
+    };
+
+    let lookups = hops.iter().map(|hop| {
+        let ip = hop.ips.first().copied();
+        task::spawn_blocking(move || ip.and_then(|ip| resolve_host(&ip.to_string())?.1))
+    });
+    let host_names = join_all(lookups).await;
+
+    let hops = (1..)
+        .zip(hops)
+        .zip(host_names)
+        .map(|((ttl, hop), host_name)| HopReport {
+            ttl,
+            ip: hop.ips.first().map(|ip| ip.to_string()),
+            host_name: host_name.unwrap_or_default(),
+            other_ips: hop.ips.iter().skip(1).map(|ip| ip.to_string()).collect(),
+            pings: hop.pings,
+            sketch: hop.sketch,
+        })
+        .collect();
+
+    TargetReport {
+        pings,
+        sketch,
+        hops,
+        ..report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, time::Duration};
+
+    use async_std::channel::Sender;
+
+    use super::*;
+    use crate::pinger::{HopReply, PingError};
+
+    /// Answers like the routers on the way to a target, `None` for hops that never answer. Stops
+    /// the schedule after a number of rounds.
+    struct Route {
+        routers: Vec<Option<IpAddr>>,
+        /// The target never answers if this is false.
+        reachable: bool,
+        rounds: u32,
+        stop: Sender<()>,
+        /// The highest TTL probed in each round.
+        probed: RefCell<Vec<u8>>,
+    }
+
+    impl HopPinger for Route {
+        async fn send_with_ttl(&self, ip: IpAddr, ttl: u8) -> Result<HopReply, PingError> {
+            let mut probed = self.probed.borrow_mut();
+            if ttl == 1 {
+                probed.push(ttl);
+                if probed.len() as u32 == self.rounds {
+                    self.stop.close();
+                }
+            }
+            *probed.last_mut().unwrap() = ttl;
+
+            let rtt = ttl as u32 * 1000;
+            match self.routers.get(ttl as usize - 1) {
+                Some(Some(from)) => Ok(HopReply {
+                    from: *from,
+                    rtt,
+                    reached: false,
+                }),
+                None if self.reachable => Ok(HopReply {
+                    from: ip,
+                    rtt,
+                    reached: true,
+                }),
+                _ => Err(PingError::Timeout),
+            }
+        }
+    }
+
+    async fn trace(routers: Vec<Option<IpAddr>>, reachable: bool) -> (TargetReport, Vec<u8>) {
+        let (schedule, stop) = Schedule::until_closed(Duration::ZERO);
+        let route = Route {
+            routers,
+            reachable,
+            rounds: 3,
+            stop,
+            probed: Default::default(),
+        };
+        let target = Target {
+            ip: [127, 0, 0, 9].into(),
+            host: None,
+            probe: Probe::Icmp,
+        };
+        let out = Output {
+            display_pings: false,
+            log: None,
+            influx: None,
+            metrics: None,
+            sketch: false,
+            dashboard: None,
+        };
+
+        let report = trace_target(&route, 0, &target, 8, &schedule, out).await;
+        (report, route.probed.into_inner())
+    }
+
+    fn ip(n: u8) -> Option<IpAddr> {
+        Some([127, 0, 0, n].into())
+    }
+
+    #[test]
+    fn hops_stop_at_the_target() {
+        let (report, probed) = task::block_on(trace(vec![ip(1), None, ip(3)], true));
+
+        // all hops are probed until the target answered
+        assert_eq!(probed, [8, 4, 4]);
+
+        let ips: Vec<_> = report.hops.iter().map(|h| h.ip.as_deref()).collect();
+        assert_eq!(
+            ips,
+            [
+                Some("127.0.0.1"),
+                None,
+                Some("127.0.0.3"),
+                Some("127.0.0.9")
+            ]
+        );
+        assert!(report.hops.iter().all(|h| h.pings.len() == 3));
+        assert!(report.hops[1]
+            .pings
+            .iter()
+            .all(|p| p.status == Status::Timeout));
+
+        // the target's pings are those of the last hop
+        let statuses: Vec<_> = report.pings.iter().map(|p| p.status.clone()).collect();
+        assert_eq!(statuses, vec![Status::Ok { rtt: 4000 }; 3]);
+    }
+
+    #[test]
+    fn silent_hops_after_the_last_answer_are_dropped() {
+        let (report, probed) = task::block_on(trace(vec![ip(1), ip(2)], false));
+
+        assert_eq!(probed, [8, 8, 8]);
+        assert_eq!(report.hops.len(), 2);
+
+        // the target is lost in every round
+        assert_eq!(report.pings.len(), 3);
+        assert!(report.pings.iter().all(|p| p.status == Status::Timeout));
+    }
+}